@@ -1,47 +1,257 @@
 use clap::ArgMatches;
 
+use crate::config::Config;
+use std::str::FromStr;
+#[cfg(feature = "tracker")]
+use story_line_counter::tracker::TrackerConfig;
+use story_line_counter::{CherryPicks, MergeMode, Period, PointsOptions, Reverts, ShallowBoundary};
+
 pub struct CollectArgs {
     pub branch: String,
     pub matcher: String,
+    pub matcher_pattern: Option<String>,
+    pub exclude_paths: Vec<String>,
     pub path: String,
-    pub points_path: Option<String>,
+    pub paths: Vec<String>,
+    pub workspace: Option<String>,
+    pub repo_name: Option<String>,
+    pub jobs: usize,
+    pub use_cache: bool,
+    pub skip_errors: bool,
+    pub submodules: bool,
+    pub authors: Vec<String>,
+    pub exclude_authors: Vec<String>,
+    pub exclude_bots: bool,
+    pub shallow_boundary: ShallowBoundary,
+    pub merge_mode: MergeMode,
+    pub reverts: Reverts,
+    pub cherry_picks: CherryPicks,
+    pub points: PointsOptions,
+    pub rollup: bool,
+    pub format: String,
 }
 
 pub struct TotalArgs {
     pub paths: Option<Vec<String>>,
-    pub points_path: Option<String>,
+    pub reverts: Reverts,
+    pub cherry_picks: CherryPicks,
+    pub points: PointsOptions,
+    pub rollup: bool,
+    pub format: String,
+}
+
+pub struct TimelineArgs {
+    pub total: TotalArgs,
+    pub period: Period,
+    pub sprints: Option<String>,
+}
+
+pub struct DiffReportArgs {
+    pub before: String,
+    pub after: String,
+    pub format: String,
 }
 
-pub fn parse_collect_args(matches: &ArgMatches) -> CollectArgs {
-    let branch = matches.value_of("branch").unwrap_or_default().to_string();
-    let matcher = matches.value_of("matcher").unwrap_or_default().to_string();
-    let path = matches.value_of("filepath").unwrap_or_default().to_string();
-    let points_path = match matches.value_of("points") {
-        Some(points_path) => Some(points_path.to_string()),
-        None => None,
+// Arguments given on the command line take precedence over the config
+pub fn parse_collect_args(matches: &ArgMatches, config: &Config) -> CollectArgs {
+    let branch = matches
+        .value_of("branch")
+        .unwrap_or(config.branch_or_default())
+        .to_string();
+    let matcher = matches
+        .value_of("matcher")
+        .unwrap_or(config.matcher_or_default())
+        .to_string();
+    let paths = match parse_values(matches, "filepath") {
+        paths if paths.is_empty() => vec![".".to_string()],
+        paths => paths,
     };
+    let jobs = value_t!(matches, "jobs", usize).unwrap_or(0);
 
     CollectArgs {
+        matcher_pattern: config.matchers.get(&matcher).cloned(),
+        exclude_paths: config.exclude_paths.clone(),
         branch,
         matcher,
-        path,
-        points_path,
+        path: paths[0].to_string(),
+        paths,
+        workspace: matches.value_of("workspace").map(|path| path.to_string()),
+        repo_name: matches.value_of("repo-name").map(|name| name.to_string()),
+        jobs,
+        use_cache: !matches.is_present("no-cache"),
+        skip_errors: matches.is_present("skip-errors"),
+        submodules: matches.is_present("submodules"),
+        authors: parse_values(matches, "author"),
+        exclude_authors: parse_values(matches, "exclude-author"),
+        exclude_bots: matches.is_present("exclude-bots"),
+        shallow_boundary: parse_choice(matches, "shallow-boundary"),
+        merge_mode: parse_choice(matches, "merge-mode"),
+        reverts: parse_choice(matches, "reverts"),
+        cherry_picks: parse_choice(matches, "cherry-picks"),
+        points: parse_points_options(matches, config),
+        rollup: matches.is_present("rollup"),
+        format: parse_format(matches, config),
     }
 }
 
-pub fn parse_total_args(matches: &ArgMatches) -> TotalArgs {
+pub fn parse_total_args(matches: &ArgMatches, config: &Config) -> TotalArgs {
     let paths = match matches.values_of("in") {
         Some(paths) => paths.map(|str| str.to_string()).collect::<Vec<String>>(),
         None => Vec::new(),
     };
 
-    let points_path = match matches.value_of("points") {
-        Some(points_path) => Some(points_path.to_string()),
-        None => None,
+    TotalArgs {
+        paths: if !paths.is_empty() { Some(paths) } else { None },
+        reverts: parse_choice(matches, "reverts"),
+        cherry_picks: parse_choice(matches, "cherry-picks"),
+        points: parse_points_options(matches, config),
+        rollup: matches.is_present("rollup"),
+        format: parse_format(matches, config),
+    }
+}
+
+pub fn parse_timeline_args(matches: &ArgMatches, config: &Config) -> TimelineArgs {
+    TimelineArgs {
+        total: parse_total_args(matches, config),
+        period: parse_choice(matches, "period"),
+        sprints: matches.value_of("sprints").map(|path| path.to_string()),
+    }
+}
+
+pub fn parse_diff_report_args(matches: &ArgMatches, config: &Config) -> DiffReportArgs {
+    DiffReportArgs {
+        before: matches.value_of("before").unwrap_or_default().to_string(),
+        after: matches.value_of("after").unwrap_or_default().to_string(),
+        format: parse_format(matches, config),
+    }
+}
+
+fn parse_format(matches: &ArgMatches, config: &Config) -> String {
+    matches
+        .value_of("format")
+        .unwrap_or(config.format_or_default())
+        .to_string()
+}
+
+// Files from the config are merged first, so files given as arguments override them
+fn parse_points_options(matches: &ArgMatches, config: &Config) -> PointsOptions {
+    let options = PointsOptions::new()
+        .points_paths(&config.points)
+        .points_paths(&parse_values(matches, "points"))
+        .alias_paths(&config.aliases)
+        .alias_paths(&parse_values(matches, "aliases"))
+        .hierarchy_paths(&config.hierarchy)
+        .hierarchy_paths(&parse_values(matches, "hierarchy"))
+        .metadata_paths(&config.metadata)
+        .metadata_paths(&parse_values(matches, "metadata"))
+        .strict_points(matches.is_present("strict-points"))
+        .reconcile(matches.is_present("reconcile"));
+
+    #[cfg(feature = "tracker")]
+    let options = options.tracker(parse_tracker_config(matches, config));
+    options
+}
+
+#[cfg(feature = "tracker")]
+fn parse_tracker_config(matches: &ArgMatches, config: &Config) -> Option<TrackerConfig> {
+    let kind = match matches.value_of("tracker") {
+        Some(kind) => kind,
+        None => return get_configured_tracker(matches, config),
     };
 
-    TotalArgs {
-        paths: if paths.len() > 0 { Some(paths) } else { None },
-        points_path,
+    Some(TrackerConfig {
+        kind: kind.to_string(),
+        url: matches
+            .value_of("tracker-url")
+            .unwrap_or_default()
+            .to_string(),
+        user: matches
+            .value_of("tracker-user")
+            .map(|user| user.to_string()),
+        token: matches
+            .value_of("tracker-token")
+            .map(|token| token.to_string()),
+        points_field: matches
+            .value_of("tracker-points-field")
+            .map(|field| field.to_string()),
+    })
+}
+
+// Repeatable values are returned in the order given, so later files override earlier ones
+fn parse_values(matches: &ArgMatches, name: &str) -> Vec<String> {
+    match matches.values_of(name) {
+        Some(paths) => paths.map(|str| str.to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+// clap only accepts the option's possible values, so anything else means it wasn't given
+fn parse_choice<T: FromStr + Default>(matches: &ArgMatches, name: &str) -> T {
+    matches
+        .value_of(name)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_default()
+}
+
+// The config's tracker, with the token still coming from the command line or environment
+#[cfg(feature = "tracker")]
+fn get_configured_tracker(matches: &ArgMatches, config: &Config) -> Option<TrackerConfig> {
+    let tracker = config.tracker.as_ref()?;
+
+    Some(TrackerConfig {
+        kind: tracker.kind.to_string(),
+        url: tracker.url.to_string(),
+        user: tracker.user.clone(),
+        token: matches
+            .value_of("tracker-token")
+            .map(|token| token.to_string()),
+        points_field: tracker.points_field.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{App, Arg};
+
+    fn matches(args: &[&str]) -> ArgMatches<'static> {
+        App::new("test")
+            .args(&[
+                Arg::with_name("branch").takes_value(true).long("branch"),
+                Arg::with_name("format").takes_value(true).long("format"),
+                Arg::with_name("points")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .long("points"),
+            ])
+            .get_matches_from(std::iter::once("test").chain(args.iter().cloned()))
+    }
+
+    #[test]
+    fn arguments_override_the_config() {
+        let config = Config {
+            branch: Some("develop".to_string()),
+            format: Some("table".to_string()),
+            points: vec!["/config/points.json".to_string()],
+            ..Config::default()
+        };
+
+        let args = parse_collect_args(
+            &matches(&["--branch", "main", "--points", "sprint.json"]),
+            &config,
+        );
+
+        assert_eq!(args.branch, "main");
+        assert_eq!(args.format, "table");
+        // files given as arguments are merged last, so they win
+        assert_eq!(
+            args.points.points_paths,
+            vec!["/config/points.json", "sprint.json"]
+        );
+
+        let args = parse_collect_args(&matches(&["--format", "jsonl"]), &config);
+        assert_eq!(args.branch, "develop");
+        assert_eq!(args.format, "jsonl");
     }
 }
@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use story_line_counter::errors::{CliError, FileError};
+
+pub const DEFAULT_BRANCH: &str = "master";
+pub const DEFAULT_MATCHER: &str = "v1";
+pub const DEFAULT_FORMAT: &str = "json";
+
+const REPO_CONFIG_FILE: &str = ".story-line-counter.toml";
+const USER_CONFIG_DIRECTORY: &str = "story-line-counter";
+const USER_CONFIG_FILE: &str = "config.toml";
+
+/// Settings read from config files. Anything given on the command line takes precedence.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub branch: Option<String>,
+    pub matcher: Option<String>,
+    pub format: Option<String>,
+    /// Git pathspecs for files whose changes aren't counted.
+    pub exclude_paths: Vec<String>,
+    /// Points files, merged before any given with --points.
+    pub points: Vec<String>,
+    /// Alias files, merged before any given with --aliases.
+    pub aliases: Vec<String>,
+    /// Hierarchy files, merged before any given with --hierarchy.
+    pub hierarchy: Vec<String>,
+    /// Story metadata exports, merged before any given with --metadata.
+    pub metadata: Vec<String>,
+    pub tracker: Option<TrackerSettings>,
+    /// Custom story number patterns by matcher name, each capturing a prefix and a number.
+    pub matchers: BTreeMap<String, String>,
+    /// The files these settings were read from, in the order they were applied.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+/// The tracker to fetch points from. The token is never read from a config file; set
+/// STORY_LINE_COUNTER_TRACKER_TOKEN or pass --tracker-token instead.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TrackerSettings {
+    pub kind: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_field: Option<String>,
+}
+
+impl Config {
+    /// Loads the user's config, then the config in the root of the repo containing `path`,
+    /// which overrides it. Missing files are skipped.
+    pub fn load(path: &str) -> Result<Config, CliError> {
+        let mut config = Config::default();
+
+        for config_path in get_config_paths(path) {
+            if config_path.is_file() {
+                config.merge(Config::load_file(&config_path)?);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn load_file(config_path: &Path) -> Result<Config, CliError> {
+        let display_path = config_path.display().to_string();
+        let bad_file =
+            |error: String| CliError::BadConfigFile(FileError::new(&display_path, error));
+
+        let contents =
+            fs::read_to_string(config_path).map_err(|error| bad_file(error.to_string()))?;
+        let mut config: Config =
+            toml::from_str(&contents).map_err(|error| bad_file(error.to_string()))?;
+
+        // files named in a config file are relative to it
+        let directory = config_path.parent().unwrap_or_else(|| Path::new("."));
+        config.points = resolve_paths(directory, &config.points);
+        config.aliases = resolve_paths(directory, &config.aliases);
+        config.hierarchy = resolve_paths(directory, &config.hierarchy);
+        config.metadata = resolve_paths(directory, &config.metadata);
+        config.sources.push(config_path.to_path_buf());
+
+        Ok(config)
+    }
+
+    /// Applies `other` over this config. Its settings replace these, and its lists of files and
+    /// excludes are added after these.
+    fn merge(&mut self, other: Config) -> &mut Self {
+        let Config {
+            branch,
+            matcher,
+            format,
+            exclude_paths,
+            points,
+            aliases,
+            hierarchy,
+            metadata,
+            tracker,
+            matchers,
+            sources,
+        } = other;
+
+        self.branch = branch.or(self.branch.take());
+        self.matcher = matcher.or(self.matcher.take());
+        self.format = format.or(self.format.take());
+        self.tracker = tracker.or(self.tracker.take());
+        self.exclude_paths.extend(exclude_paths);
+        self.points.extend(points);
+        self.aliases.extend(aliases);
+        self.hierarchy.extend(hierarchy);
+        self.metadata.extend(metadata);
+        self.matchers.extend(matchers);
+        self.sources.extend(sources);
+
+        self
+    }
+
+    /// The settings in effect once defaults are filled in.
+    pub fn effective(&self) -> Config {
+        let mut effective = self.clone();
+        effective.branch = Some(self.branch_or_default().to_string());
+        effective.matcher = Some(self.matcher_or_default().to_string());
+        effective.format = Some(self.format_or_default().to_string());
+        effective
+    }
+
+    pub fn branch_or_default(&self) -> &str {
+        self.branch.as_deref().unwrap_or(DEFAULT_BRANCH)
+    }
+
+    pub fn matcher_or_default(&self) -> &str {
+        self.matcher.as_deref().unwrap_or(DEFAULT_MATCHER)
+    }
+
+    pub fn format_or_default(&self) -> &str {
+        self.format.as_deref().unwrap_or(DEFAULT_FORMAT)
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.sources.is_empty() {
+            writeln!(f, "# No config files found")?;
+        }
+        for source in self.sources.iter() {
+            writeln!(f, "# Read from {}", source.display())?;
+        }
+
+        let settings = toml::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", settings)
+    }
+}
+
+// The user's config comes first so the repo's config can override it
+fn get_config_paths(path: &str) -> Vec<PathBuf> {
+    let mut config_paths = Vec::new();
+    if let Some(config_home) = get_config_home() {
+        config_paths.push(
+            config_home
+                .join(USER_CONFIG_DIRECTORY)
+                .join(USER_CONFIG_FILE),
+        );
+    }
+    config_paths.push(get_repo_root(path).join(REPO_CONFIG_FILE));
+    config_paths
+}
+
+// $XDG_CONFIG_HOME, falling back to ~/.config as the XDG spec does
+fn get_config_home() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => Some(PathBuf::from(config_home)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+    }
+}
+
+// The working directory of the repo containing `path`, or `path` itself outside of a repo
+fn get_repo_root(path: &str) -> PathBuf {
+    git2::Repository::discover(path)
+        .ok()
+        .and_then(|repository| repository.workdir().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from(path))
+}
+
+fn resolve_paths(directory: &Path, paths: &[String]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| directory.join(path).to_string_lossy().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_config(directory: &Path, contents: &str) -> PathBuf {
+        let config_path = directory.join(REPO_CONFIG_FILE);
+        fs::write(&config_path, contents).unwrap();
+        config_path
+    }
+
+    #[test]
+    fn repo_config_overrides_user_config() {
+        let user_dir = TempDir::new().unwrap();
+        let repo_dir = TempDir::new().unwrap();
+        let user_config = write_config(
+            user_dir.path(),
+            "branch = \"develop\"\nformat = \"table\"\npoints = [\"user.json\"]\n",
+        );
+        let repo_config = write_config(
+            repo_dir.path(),
+            "branch = \"main\"\npoints = [\"repo.json\"]\n",
+        );
+
+        let mut config = Config::default();
+        config.merge(Config::load_file(&user_config).unwrap());
+        config.merge(Config::load_file(&repo_config).unwrap());
+
+        assert_eq!(config.branch_or_default(), "main");
+        assert_eq!(config.format_or_default(), "table");
+        assert_eq!(config.matcher_or_default(), DEFAULT_MATCHER);
+        assert_eq!(
+            config.points,
+            vec![
+                user_dir
+                    .path()
+                    .join("user.json")
+                    .to_string_lossy()
+                    .to_string(),
+                repo_dir
+                    .path()
+                    .join("repo.json")
+                    .to_string_lossy()
+                    .to_string(),
+            ]
+        );
+        assert_eq!(config.sources, vec![user_config, repo_config]);
+    }
+
+    #[test]
+    fn resolves_files_relative_to_the_config() {
+        let dir = TempDir::new().unwrap();
+        let config_path = write_config(
+            dir.path(),
+            "aliases = [\"stories/aliases.json\"]\nmetadata = [\"/exports/stories.csv\"]\n",
+        );
+
+        let config = Config::load_file(&config_path).unwrap();
+
+        assert_eq!(
+            config.aliases,
+            vec![dir
+                .path()
+                .join("stories/aliases.json")
+                .to_string_lossy()
+                .to_string()]
+        );
+        assert_eq!(config.metadata, vec!["/exports/stories.csv"]);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        let dir = TempDir::new().unwrap();
+
+        for contents in [
+            "brnach = \"main\"\n",
+            "[tracker]\nkind = \"jira\"\nurl = \"https://jira\"\ntoken = \"secret\"\n",
+        ] {
+            let config_path = write_config(dir.path(), contents);
+            match Config::load_file(&config_path) {
+                Err(CliError::BadConfigFile(error)) => {
+                    assert!(error.description.contains("unknown field"), "{}", error)
+                }
+                other => panic!("expected a bad config file, got {:?}", other),
+            }
+        }
+    }
+}
@@ -34,11 +34,186 @@ impl fmt::Display for InputError {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct TrackerError {
+    description: String,
+}
+
+impl From<String> for TrackerError {
+    fn from(description: String) -> TrackerError {
+        TrackerError { description }
+    }
+}
+
+impl From<&str> for TrackerError {
+    fn from(description: &str) -> TrackerError {
+        TrackerError {
+            description: description.to_string(),
+        }
+    }
+}
+
+impl Error for TrackerError {}
+
+impl fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description)?;
+        Ok(())
+    }
+}
+
+/// A collection, points or metadata file that couldn't be opened or parsed.
+#[derive(Debug, Clone)]
+pub struct FileError {
+    pub path: String,
+    pub description: String,
+}
+
+impl FileError {
+    pub fn new<E: fmt::Display>(path: &str, error: E) -> FileError {
+        FileError {
+            path: path.to_string(),
+            description: error.to_string(),
+        }
+    }
+
+    pub fn json(path: &str, error: serde_json::Error) -> FileError {
+        FileError::new(path, describe_json_error(&error))
+    }
+
+    pub fn csv(path: &str, error: csv::Error) -> FileError {
+        FileError::new(path, describe_csv_error(&error))
+    }
+}
+
+impl Error for FileError {}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.description)?;
+        Ok(())
+    }
+}
+
+/// A commit that couldn't be read or diffed. `commit` is None when the history walk itself failed.
+#[derive(Debug)]
+pub struct CommitError {
+    pub commit: Option<String>,
+    pub error: git2::Error,
+}
+
+impl CommitError {
+    pub fn commit(commit: String, error: git2::Error) -> CommitError {
+        CommitError {
+            commit: Some(commit),
+            error,
+        }
+    }
+
+    pub fn walk(error: git2::Error) -> CommitError {
+        CommitError {
+            commit: None,
+            error,
+        }
+    }
+}
+
+impl Error for CommitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.commit {
+            Some(commit) => write!(f, "Could not read commit {}: {}", commit, self.error)?,
+            None => write!(f, "Could not walk the commit history: {}", self.error)?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum CliError {
     Git(git2::Error),
     IO(std::io::Error),
+    /// Invalid or missing command line input.
     Input(InputError),
+    /// No git repo at or above the given path.
+    RepoNotFound(String, git2::Error),
+    /// The branch to walk doesn't exist in the repo.
+    BranchNotFound(String, git2::Error),
+    /// A DiffCollection file that couldn't be read.
+    BadInputFile(FileError),
+    /// A points, alias or hierarchy file that couldn't be read, or has conflicting points.
+    BadPointsFile(FileError),
+    /// A story metadata export that couldn't be read.
+    BadMetadataFile(FileError),
+    /// A story number or author pattern that doesn't compile.
+    Regex(regex::Error),
+    /// A config file that couldn't be read.
+    BadConfigFile(FileError),
+    Tracker(TrackerError),
+    Commit(CommitError),
+}
+
+impl CliError {
+    /// The process exit code for each category of error, so scripts can tell them apart. 1 is
+    /// left to the argument parser, which exits with it on usage errors.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CliError::Input(_) => 2,
+            CliError::RepoNotFound(..) => 3,
+            CliError::BranchNotFound(..) => 4,
+            CliError::BadInputFile(_) => 5,
+            CliError::BadPointsFile(_) => 6,
+            CliError::Regex(_) => 7,
+            CliError::Commit(_) => 8,
+            CliError::Tracker(_) => 9,
+            CliError::BadConfigFile(_) => 10,
+            CliError::BadMetadataFile(_) => 11,
+            CliError::Git(_) | CliError::IO(_) => 12,
+        }
+    }
+
+    /// A stable name for the category of error, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            CliError::Git(_) => "git",
+            CliError::IO(_) => "io",
+            CliError::Input(_) => "input",
+            CliError::RepoNotFound(..) => "repo_not_found",
+            CliError::BranchNotFound(..) => "branch_not_found",
+            CliError::BadInputFile(_) => "bad_input_file",
+            CliError::BadPointsFile(_) => "bad_points_file",
+            CliError::BadMetadataFile(_) => "bad_metadata_file",
+            CliError::Regex(_) => "regex",
+            CliError::Commit(_) => "commit",
+            CliError::Tracker(_) => "tracker",
+            CliError::BadConfigFile(_) => "bad_config_file",
+        }
+    }
+}
+
+fn describe_json_error(error: &serde_json::Error) -> String {
+    match error.classify() {
+        Category::Io => format!("An IO error occurred: {}", error),
+        Category::Syntax => format!("A syntax error occurred: {}", error),
+        Category::Data => format!("A data error occurred: {}", error),
+        Category::Eof => format!("Unexpected EOF. {}", error),
+    }
+}
+
+fn describe_csv_error(error: &csv::Error) -> String {
+    match error.position() {
+        Some(position) => format!(
+            "A CSV error occurred on line {}: {}",
+            position.line(),
+            error
+        ),
+        None => format!("A CSV error occurred: {}", error),
+    }
 }
 
 impl From<git2::Error> for CliError {
@@ -49,13 +224,11 @@ impl From<git2::Error> for CliError {
 
 impl From<serde_json::Error> for CliError {
     fn from(error: serde_json::Error) -> CliError {
-        let description = match error.classify() {
-            Category::Io => format!("An IO error occurred: {}", error.description()),
-            Category::Syntax => format!("A syntax error occurred: {}", error.description()),
-            Category::Data => format!("A data error occurred: {}", error.description()),
-            Category::Eof => format!("Unexpected EOF. {}", error.description()),
-        };
-        CliError::Input(InputError::from(description))
+        // failures writing output, e.g. to a closed pipe, aren't the input's fault
+        if error.is_io() {
+            return CliError::IO(error.into());
+        }
+        CliError::Input(InputError::from(describe_json_error(&error)))
     }
 }
 
@@ -71,22 +244,56 @@ impl From<InputError> for CliError {
     }
 }
 
+impl From<regex::Error> for CliError {
+    fn from(err: regex::Error) -> CliError {
+        CliError::Regex(err)
+    }
+}
+
+impl From<TrackerError> for CliError {
+    fn from(err: TrackerError) -> CliError {
+        CliError::Tracker(err)
+    }
+}
+
 impl fmt::Display for CliError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             CliError::Git(ref err) => err.fmt(f),
             CliError::IO(ref err) => err.fmt(f),
             CliError::Input(ref err) => err.fmt(f),
+            CliError::RepoNotFound(ref path, _) => {
+                write!(f, "Could not find a git repo at {}", path)
+            }
+            CliError::BranchNotFound(ref branch, _) => {
+                write!(f, "Could not find a local branch named {}", branch)
+            }
+            CliError::BadInputFile(ref err) => err.fmt(f),
+            CliError::BadPointsFile(ref err) => err.fmt(f),
+            CliError::BadMetadataFile(ref err) => err.fmt(f),
+            CliError::Regex(ref err) => write!(f, "Invalid pattern: {}", err),
+            CliError::BadConfigFile(ref err) => err.fmt(f),
+            CliError::Tracker(ref err) => err.fmt(f),
+            CliError::Commit(ref err) => err.fmt(f),
         }
     }
 }
 
 impl Error for CliError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
-            CliError::Git(ref err) => err.description(),
-            CliError::IO(ref err) => err.description(),
-            CliError::Input(ref err) => err.description(),
+            CliError::Git(ref err) => Some(err),
+            CliError::IO(ref err) => Some(err),
+            CliError::Input(ref err) => Some(err),
+            CliError::RepoNotFound(_, ref err) => Some(err),
+            CliError::BranchNotFound(_, ref err) => Some(err),
+            CliError::BadInputFile(ref err) => Some(err),
+            CliError::BadPointsFile(ref err) => Some(err),
+            CliError::BadMetadataFile(ref err) => Some(err),
+            CliError::Regex(ref err) => Some(err),
+            CliError::BadConfigFile(ref err) => Some(err),
+            CliError::Tracker(ref err) => Some(err),
+            CliError::Commit(ref err) => Some(err),
         }
     }
 }
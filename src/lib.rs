@@ -0,0 +1,30 @@
+//! Counts the lines changed for each story in a git repo, by reading story numbers from commit
+//! summaries. This is the library behind the `story-line-counter` command.
+//!
+//! ```no_run
+//! use story_line_counter::{run, CollectOptions};
+//!
+//! let options = CollectOptions::new().branch("main").matcher("jira").jobs(4);
+//! let totals = run("/path/to/repo", &options)?;
+//! for (story_number, total) in totals.totals.iter() {
+//!     println!("{}: {} lines", story_number, total.insertions + total.deletions);
+//! }
+//! # Ok::<(), story_line_counter::errors::CliError>(())
+//! ```
+
+pub mod errors;
+pub mod repo;
+#[cfg(feature = "tracker")]
+pub mod tracker;
+
+pub use crate::repo::aliases;
+pub use crate::repo::comparison;
+pub use crate::repo::diff;
+pub use crate::repo::hierarchy;
+pub use crate::repo::inputs;
+pub use crate::repo::metadata;
+pub use crate::repo::timeline;
+pub use crate::repo::{
+    clear_cache, collect, collect_each, filter_diffs, is_partial_clone, run, total, CherryPicks,
+    CollectOptions, MergeMode, Period, PointsOptions, Reverts, ShallowBoundary, TotalAccumulator,
+};
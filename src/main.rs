@@ -1,197 +1,702 @@
 #[macro_use]
 extern crate clap;
 
-use crate::repo::{collect, run, total};
+use story_line_counter::{
+    clear_cache, collect, collect_each, filter_diffs, is_partial_clone, CherryPicks,
+    CollectOptions, MergeMode, Period, PointsOptions, Reverts, ShallowBoundary, TotalAccumulator,
+};
 
-use crate::args_parser::{parse_collect_args, parse_total_args, CollectArgs, TotalArgs};
-use crate::errors::{CliError, InputError};
-use crate::repo::diff::{
-    DiffCollection, DiffResult, DiffTotalCollection, Pointable, StoryPointCollection,
+use crate::args_parser::{
+    parse_collect_args, parse_diff_report_args, parse_timeline_args, parse_total_args, CollectArgs,
+    DiffReportArgs, TimelineArgs, TotalArgs,
 };
-use clap::{App, Arg, SubCommand};
-use std::fs::File;
-use std::io::BufReader;
+use crate::config::{Config, DEFAULT_FORMAT};
+use crate::workspace::Workspace;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+use std::io;
+use std::io::{IsTerminal, Write};
 use std::path::Path;
+use std::process;
+use story_line_counter::comparison::compare;
+use story_line_counter::diff::{DiffCollection, DiffResult, DiffTotalCollection, Pointable};
+use story_line_counter::errors::{CliError, FileError, InputError};
+use story_line_counter::inputs::{load_json, read_inputs, STDIN_PATH};
+use story_line_counter::timeline::{Periods, SprintCalendar, TimelineAccumulator};
 
 mod args_parser;
-mod errors;
-mod repo;
+mod config;
+mod workspace;
+
+// The output formats of collect, and of the commands writing a report
+const COLLECT_FORMATS: &[&str] = &["json", "jsonl"];
+const REPORT_FORMATS: &[&str] = &["json", "table"];
 
 fn main() {
     let collect_args = [
         Arg::with_name("branch")
-            .help("The branch name to scan.")
-            .default_value("master")
+            .help("The branch name to scan. Defaults to master, or the branch in a config file.")
+            .takes_value(true)
             .long("branch")
             .short("b"),
         Arg::with_name("matcher")
-            .help("The issue tracker counting schema to use.")
-            .possible_values(&["v1", "jira"])
-            .default_value("v1")
+            .help("The issue tracker counting schema to use: v1, jira, or a matcher defined in a config file. Defaults to v1.")
+            .takes_value(true)
             .long("matcher")
             .short("m"),
-        Arg::with_name("filepath")
-            .help("The path to the git repo to scan.")
+        Arg::with_name("jobs")
+            .help("The number of threads to diff commits with. Defaults to one per CPU.")
+            .takes_value(true)
+            .validator(|jobs| match jobs.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => Ok(()),
+                _ => Err("jobs must be a positive number".to_string()),
+            })
+            .long("jobs")
+            .short("j"),
+        Arg::with_name("skip-errors")
+            .help("Skip and report commits that can't be read, e.g. in shallow or partial clones.")
+            .long("skip-errors"),
+        Arg::with_name("merge-mode")
+            .help("How to count merged branches: follow first parents only, count every commit, or count each merge as one unit.")
+            .possible_values(MergeMode::VALUES)
+            .default_value("first-parent")
+            .long("merge-mode"),
+        Arg::with_name("shallow-boundary")
+            .help("Whether to exclude or flag the boundary commits of a shallow clone, which count the whole tree.")
+            .possible_values(ShallowBoundary::VALUES)
+            .default_value("exclude")
+            .long("shallow-boundary"),
+        Arg::with_name("no-cache")
+            .help("Diff every commit instead of reusing results cached by earlier runs. Implied by --format jsonl.")
+            .long("no-cache"),
+        Arg::with_name("author")
+            .help("Only count commits whose author or committer matches this regex, or an email domain like @example.com. May be repeated.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .long("author"),
+        Arg::with_name("exclude-author")
+            .help("Leave out commits whose author or committer matches this regex, or an email domain like @example.com. May be repeated.")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .long("exclude-author"),
+        Arg::with_name("exclude-bots")
+            .help("Leave out commits by well known bots, like dependabot, renovate and GitHub apps.")
+            .long("exclude-bots"),
+        Arg::with_name("submodules")
+            .help("When a commit updates a submodule, also count the changes between its old and new commits.")
+            .long("submodules"),
+    ];
+
+    let filter_args = [
+        Arg::with_name("reverts")
+            .help("Whether to keep reverted commits and their reverts, or exclude both.")
+            .possible_values(Reverts::VALUES)
+            .default_value("keep")
+            .long("reverts"),
+        Arg::with_name("cherry-picks")
+            .help("Whether to keep cherry-picked copies of a change, or count the change once.")
+            .possible_values(CherryPicks::VALUES)
+            .default_value("keep")
+            .long("cherry-picks"),
+    ];
+
+    let totals_args = vec![
+        Arg::with_name("points")
+            .help("The path to a points file to merge. May be repeated; later files override earlier ones.")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1)
+            .long("points")
+            .short("p"),
+        Arg::with_name("aliases")
+            .help("The path to a file mapping old story numbers to their current ones. May be repeated.")
+            .takes_value(true)
             .required(false)
-            .default_value(".")
-            .index(1),
+            .multiple(true)
+            .number_of_values(1)
+            .long("aliases"),
+        Arg::with_name("metadata")
+            .help("The path to a JSON or CSV story metadata export to merge. May be repeated.")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1)
+            .long("metadata"),
+        Arg::with_name("strict-points")
+            .help("Fail instead of warning when a points, alias or hierarchy file lists a story with conflicting values.")
+            .long("strict-points"),
+        Arg::with_name("format")
+            .help("The output format. Defaults to json, or the format in a config file.")
+            .possible_values(REPORT_FORMATS)
+            .takes_value(true)
+            .long("format")
+            .short("f"),
+    ];
+    // Fetching points from an issue tracker needs the tracker feature
+    #[cfg(feature = "tracker")]
+    let totals_args: Vec<Arg> = totals_args
+        .into_iter()
+        .chain([
+            Arg::with_name("tracker")
+                .help("Fetch points for the collected stories from an issue tracker.")
+                .possible_values(&["jira", "v1", "file"])
+                .takes_value(true)
+                .requires("tracker-url")
+                .long("tracker"),
+            Arg::with_name("tracker-url")
+                .help("The tracker's base URL, or the points file served by the file tracker.")
+                .takes_value(true)
+                .long("tracker-url"),
+            Arg::with_name("tracker-user")
+                .help("The user to authenticate as. Jira Cloud needs this alongside an API token.")
+                .takes_value(true)
+                .long("tracker-user"),
+            Arg::with_name("tracker-token")
+                .help("The API token for the tracker.")
+                .takes_value(true)
+                .env("STORY_LINE_COUNTER_TRACKER_TOKEN")
+                .hide_env_values(true)
+                .long("tracker-token"),
+            Arg::with_name("tracker-points-field")
+                .help("The Jira field holding story points. Defaults to customfield_10002.")
+                .takes_value(true)
+                .long("tracker-points-field"),
+        ])
+        .collect();
+
+    // Options that shape a totals report, which a timeline has no place for
+    let report_args = [
+        Arg::with_name("hierarchy")
+            .help("The path to a file mapping stories to their parent features or epics. May be repeated.")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1)
+            .long("hierarchy"),
+        Arg::with_name("rollup")
+            .help("Total stories under the top of their hierarchy, listing each story under its parent.")
+            .long("rollup"),
+        Arg::with_name("reconcile")
+            .help("Also list pointed stories with no commits and committed stories with no points.")
+            .long("reconcile"),
     ];
 
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("error-format")
+                .help("How to print errors to stderr. The exit code tells errors apart either way.")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .global(true)
+                .long("error-format"),
+        )
         .subcommands(vec![
             SubCommand::with_name("collect")
                 .version(crate_version!())
                 .author(crate_authors!())
                 .about("Parses a git repo and outputs DiffCollection objects.")
-                .args(&collect_args),
+                .args(&collect_args)
+                .args(&filter_args)
+                .args(&[
+                    Arg::with_name("filepath")
+                        .help("The path to the git repo to scan.")
+                        .required(false)
+                        .default_value(".")
+                        .index(1),
+                    Arg::with_name("repo-name")
+                        .help("Tag each diff result with this repo name, so totals of several collections are broken down by repo.")
+                        .takes_value(true)
+                        .long("repo-name"),
+                ])
+                .arg(
+                    Arg::with_name("format")
+                        .help("The output format. jsonl writes one DiffResult per line as commits are diffed. Defaults to json, or the format in a config file.")
+                        .possible_values(COLLECT_FORMATS)
+                        .takes_value(true)
+                        .long("format")
+                        .short("f"),
+                ),
             SubCommand::with_name("run")
                 .version(crate_version!())
                 .author(crate_authors!())
-                .about("Opens a repo and outputs a DiffTotalCollection.")
+                .about("Opens one or more repos and outputs a DiffTotalCollection.")
                 .args(&collect_args)
-                .arg(Arg::with_name("points")
-                         .help("The path to a points file to merge.")
-                         .takes_value(true)
-                         .required(false)
-                         .long("points")
-                         .short("p")),
+                .args(&[
+                    Arg::with_name("filepath")
+                        .help("The paths to the git repos to scan. Defaults to the current directory. With more than one, totals are broken down by repo.")
+                        .required(false)
+                        .multiple(true)
+                        .index(1),
+                    Arg::with_name("workspace")
+                        .help("A JSON manifest listing the repos to scan, with optional names and branches.")
+                        .takes_value(true)
+                        .conflicts_with("filepath")
+                        .long("workspace")
+                        .short("w"),
+                ])
+                .args(&filter_args)
+                .args(&totals_args)
+                .args(&report_args),
             SubCommand::with_name("total")
                 .version(crate_version!())
                 .author(crate_authors!())
                 .about("Loads a DiffCollection file and outputs a DiffTotalCollection.")
+                .args(&[Arg::with_name("in")
+                    .help("The input files to use. Reads stdin if none are given, or for '-'.")
+                    .takes_value(true)
+                    .required(false)
+                    .multiple(true)])
+                .args(&filter_args)
+                .args(&totals_args)
+                .args(&report_args),
+            SubCommand::with_name("timeline")
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Loads DiffCollection files and outputs lines, commits and points completed per period.")
                 .args(&[
                     Arg::with_name("in")
-                        .help("The input file to use.")
+                        .help("The input files to use. Reads stdin if none are given, or for '-'.")
                         .takes_value(true)
                         .required(false)
                         .multiple(true),
-                    Arg::with_name("points")
-                        .help("The path to a points file to merge.")
+                    Arg::with_name("period")
+                        .help("The calendar period to bucket commits by, in UTC. Defaults to week.")
+                        .possible_values(Period::VALUES)
                         .takes_value(true)
-                        .required(false)
-                        .long("points")
-                        .short("p"),
+                        .long("period"),
+                    Arg::with_name("sprints")
+                        .help("A JSON sprint calendar to bucket commits by, in place of a calendar period.")
+                        .takes_value(true)
+                        .conflicts_with("period")
+                        .long("sprints"),
+                ])
+                .args(&filter_args)
+                .args(&totals_args),
+            SubCommand::with_name("diff-report")
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Compares two DiffTotalCollection files and outputs the stories that changed.")
+                .args(&[
+                    Arg::with_name("before")
+                        .help("The older DiffTotalCollection file.")
+                        .required(true)
+                        .index(1),
+                    Arg::with_name("after")
+                        .help("The newer DiffTotalCollection file.")
+                        .required(true)
+                        .index(2),
+                    Arg::with_name("format")
+                        .help("The output format. Defaults to json, or the format in a config file.")
+                        .possible_values(REPORT_FORMATS)
+                        .takes_value(true)
+                        .long("format")
+                        .short("f"),
                 ]),
+            SubCommand::with_name("cache")
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Manages the diff cache kept in a repo's git directory.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Deletes the diff cache so the next run diffs every commit.")
+                        .arg(
+                            Arg::with_name("filepath")
+                                .help("The path to the git repo.")
+                                .default_value(".")
+                                .index(1),
+                        ),
+                ),
+            SubCommand::with_name("config")
+                .version(crate_version!())
+                .author(crate_authors!())
+                .about("Shows the settings read from config files.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Prints the settings in effect for a repo, after merging its config files.")
+                        .arg(
+                            Arg::with_name("filepath")
+                                .help("The path to the git repo.")
+                                .default_value(".")
+                                .index(1),
+                        ),
+                ),
         ])
         .get_matches();
 
     let program_result = match matches.subcommand() {
-        ("collect", Some(collect_args)) => collect_command(&parse_collect_args(collect_args)),
-        ("total", Some(total_args)) => total_command(&parse_total_args(total_args)),
-        ("run", collect_args) => {
-            if collect_args.is_some() {
-                run_command(&parse_collect_args(collect_args.unwrap()))
-            } else {
-                let default = CollectArgs {
-                    branch: "master".to_string(),
-                    matcher: "v1".to_string(),
-                    path: ".".to_string(),
-                    points_path: None,
-                };
-                run_command(&default)
+        ("collect", Some(collect_args)) => load_config(collect_args)
+            .and_then(|config| collect_command(&parse_collect_args(collect_args, &config))),
+        ("total", Some(total_args)) => load_config(total_args)
+            .and_then(|config| total_command(&parse_total_args(total_args, &config))),
+        ("run", Some(run_args)) => load_config(run_args)
+            .and_then(|config| run_command(&parse_collect_args(run_args, &config))),
+        ("timeline", Some(timeline_args)) => load_config(timeline_args)
+            .and_then(|config| timeline_command(&parse_timeline_args(timeline_args, &config))),
+        ("diff-report", Some(report_args)) => load_config(report_args)
+            .and_then(|config| diff_report_command(&parse_diff_report_args(report_args, &config))),
+        ("cache", Some(cache_args)) => match cache_args.subcommand() {
+            ("clear", Some(clear_args)) => {
+                clear_cache_command(clear_args.value_of("filepath").unwrap_or("."))
             }
+            _ => unreachable!("clap requires a cache subcommand"),
         },
-        _ => {
-            println!("Please specify a valid subcommand: collect, total, or run");
-            Ok(())
-        }
+        ("config", Some(config_args)) => match config_args.subcommand() {
+            ("show", Some(show_args)) => show_config_command(show_args),
+            _ => unreachable!("clap requires a config subcommand"),
+        },
+        _ => unreachable!("clap requires a subcommand"),
     };
 
-    match program_result {
-        Err(error) => {
-            eprintln!("An error occurred: {}", error.to_string());
-        }
-        _ => (),
+    if let Err(error) = program_result {
+        let error_format = matches
+            .subcommand()
+            .1
+            .and_then(|subcommand| subcommand.value_of("error-format"))
+            .or_else(|| matches.value_of("error-format"))
+            .unwrap_or("text");
+        print_error(&error, error_format);
+        process::exit(error.exit_code());
+    }
+}
+
+fn print_error(error: &CliError, format: &str) {
+    match format {
+        "json" => eprintln!(
+            "{}",
+            json!({
+                "error": {
+                    "kind": error.kind(),
+                    "message": error.to_string(),
+                    "exit_code": error.exit_code(),
+                }
+            })
+        ),
+        _ => eprintln!("An error occurred: {}", error),
     }
 }
 
 fn total_command(args: &TotalArgs) -> Result<(), CliError> {
-    let TotalArgs { paths, points_path } = args;
+    let format = command_format(&args.format, REPORT_FORMATS);
+    let mut accumulator = get_accumulator(&args.points, args.rollup)?;
+    read_total_inputs(args, |diff_collection| {
+        accumulator.add_collection(diff_collection)
+    })?;
+
+    let mut diff_total_collection = accumulator.finish();
+    args.points.add_points(&mut diff_total_collection)?;
+
+    print_totals(&diff_total_collection, format)
+}
+
+fn timeline_command(args: &TimelineArgs) -> Result<(), CliError> {
+    let format = command_format(&args.total.format, REPORT_FORMATS);
+    let periods = match &args.sprints {
+        Some(sprints_path) => {
+            let calendar: SprintCalendar = load_json(sprints_path, CliError::BadConfigFile)?;
+            Periods::sprints(&calendar)
+                .map_err(|error| CliError::BadConfigFile(FileError::new(sprints_path, error)))?
+        }
+        None => Periods::calendar(args.period),
+    };
+
+    // Points are fetched and merged onto the totals, then completed in each story's last period
+    let aliases = args.total.points.load_aliases()?;
+    let mut accumulator = TotalAccumulator::new().with_aliases(aliases.clone());
+    let mut timeline_accumulator = TimelineAccumulator::new(periods).with_aliases(aliases);
+    read_total_inputs(&args.total, |diff_collection| {
+        timeline_accumulator.add_collection(&diff_collection);
+        accumulator.add_collection(diff_collection);
+    })?;
+
+    let mut diff_total_collection = accumulator.finish();
+    let points_index = args.total.points.add_points(&mut diff_total_collection)?;
+    let mut timeline = timeline_accumulator.finish();
+    timeline.add_points(&points_index);
+
+    match format {
+        "table" => print!("{}", timeline),
+        _ => println!("{}", serde_json::to_string(&timeline)?),
+    }
+    Ok(())
+}
+
+fn diff_report_command(args: &DiffReportArgs) -> Result<(), CliError> {
+    let format = command_format(&args.format, REPORT_FORMATS);
+    let before: DiffTotalCollection = load_json(&args.before, CliError::BadInputFile)?;
+    let after: DiffTotalCollection = load_json(&args.after, CliError::BadInputFile)?;
+    let comparison = compare(&before, &after);
+
+    match format {
+        "table" => print!("{}", comparison),
+        _ => println!("{}", serde_json::to_string(&comparison)?),
+    }
+    Ok(())
+}
 
-    let file_paths = match paths {
-        Some(paths) => Ok(paths
-            .into_iter()
-            .map(|string| Path::new(string))
-            .collect::<Vec<&Path>>()),
+// With no paths the input is piped in, unless there's nothing to pipe from
+fn read_total_inputs<F>(args: &TotalArgs, add: F) -> Result<(), CliError>
+where
+    F: FnMut(DiffCollection),
+{
+    let stdin_paths = vec![STDIN_PATH.to_string()];
+    let paths = match &args.paths {
+        Some(paths) => Ok(paths),
+        None if !io::stdin().is_terminal() => Ok(&stdin_paths),
         None => Err(InputError::from(
-            "You must specify at least one input file path.",
+            "You must specify at least one input file path, or pipe collect output in.",
         )),
     }?;
 
-    let mut collections: Vec<DiffCollection> = Vec::new();
-    for path in file_paths {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let collection = serde_json::from_reader(reader)?;
-        collections.push(collection);
+    read_inputs(paths, args.reverts, args.cherry_picks, add)
+}
+
+fn collect_command(args: &CollectArgs) -> Result<(), CliError> {
+    warn_partial_clone(&args.path, args.skip_errors)?;
+    if command_format(&args.format, COLLECT_FORMATS) == "jsonl" {
+        return collect_lines_command(args);
     }
 
-    let mut diff_results: Vec<DiffResult> = Vec::new();
-    for collection in collections {
-        collection.diffs.into_iter().for_each(|diff_result| {
-            diff_results.push(diff_result);
-        })
+    let diff_collection = collect(&args.path, &get_collect_options(args))?;
+    for skipped_commit in diff_collection.skipped_commits.iter() {
+        eprintln!("Warning: skipped commit {}", skipped_commit);
     }
-    let diff_collection = DiffCollection {
-        diffs: diff_results,
-    };
-    let mut diff_total_collection = total(diff_collection)?;
-    add_points(&mut diff_total_collection, points_path)?;
+    diff_collection
+        .diffs
+        .iter()
+        .for_each(warn_skipped_submodules);
+    warn_truncated_history(&diff_collection.history_truncated_at);
 
-    let json = serde_json::to_string(&diff_total_collection)?;
+    let json = serde_json::to_string(&diff_collection)?;
     println!("{}", json);
     Ok(())
 }
 
-fn collect_command(args: &CollectArgs) -> Result<(), CliError> {
-    let CollectArgs {
-        branch,
-        matcher,
-        path,
-        points_path: _points_path,
-    } = args;
-    let diff_collection = collect(path, branch, matcher)?;
+// Writes each diff result as its own line as soon as it's ready. Skipped commits, truncated
+// history and excluded counts are only known at the end, so they follow as a final
+// DiffCollection line with no diffs.
+fn collect_lines_command(args: &CollectArgs) -> Result<(), CliError> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
 
-    let json = serde_json::to_string(&diff_collection)?;
-    println!("{}", json);
+    // the cache keeps every diff in memory, which streaming is meant to avoid
+    let options = get_collect_options(args).use_cache(false);
+    let collected = collect_each(&args.path, &options, |diff_result| {
+        warn_skipped_submodules(&diff_result);
+        serde_json::to_writer(&mut out, &diff_result)?;
+        writeln!(out)?;
+        Ok(())
+    });
+    let diff_collection = match collected {
+        // the reader stopped early, e.g. `| head`, which isn't a failure
+        Err(CliError::IO(error)) if error.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+        collected => collected?,
+    };
+
+    for skipped_commit in diff_collection.skipped_commits.iter() {
+        eprintln!("Warning: skipped commit {}", skipped_commit);
+    }
+    warn_truncated_history(&diff_collection.history_truncated_at);
+
+    if !diff_collection.skipped_commits.is_empty()
+        || !diff_collection.history_truncated_at.is_empty()
+        || diff_collection.excluded_by_author > 0
+    {
+        serde_json::to_writer(&mut out, &diff_collection)?;
+        writeln!(out)?;
+    }
     Ok(())
 }
 
 fn run_command(args: &CollectArgs) -> Result<(), CliError> {
     let CollectArgs {
-        branch,
-        matcher,
-        path,
-        points_path,
+        workspace,
+        reverts,
+        cherry_picks,
+        points,
+        format,
+        ..
     } = args;
+    let format = command_format(format, REPORT_FORMATS);
 
-    let mut diff_total_collection = run(path, branch, matcher)?;
-    add_points(&mut diff_total_collection, points_path)?;
+    // A single repo's diffs aren't tagged, so its output is the same as it always was
+    let workspace = match workspace {
+        Some(manifest_path) => Some(Workspace::load(manifest_path)?),
+        None if args.paths.len() > 1 => Some(Workspace::from_paths(&args.paths)?),
+        None => None,
+    };
+    let diff_collection = match workspace {
+        Some(workspace) => {
+            // Reverts and cherry-picks are matched across repos once they're combined
+            let mut combined = DiffCollection::default();
+            for repo in workspace.repos.iter() {
+                let mut options = get_collect_options(args)
+                    .reverts(Reverts::Keep)
+                    .cherry_picks(CherryPicks::Keep);
+                if let Some(name) = &repo.name {
+                    options = options.repo_name(name);
+                }
+                if let Some(branch) = &repo.branch {
+                    options = options.branch(branch);
+                }
 
-    let json = serde_json::to_string(&diff_total_collection)?;
-    println!("{}", json);
+                warn_partial_clone(&repo.path, args.skip_errors)?;
+                combined.append(collect(&repo.path, &options)?);
+            }
+            filter_diffs(&mut combined, *reverts, *cherry_picks);
+            combined
+        }
+        None => {
+            warn_partial_clone(&args.path, args.skip_errors)?;
+            collect(&args.path, &get_collect_options(args))?
+        }
+    };
+    let mut accumulator = get_accumulator(points, args.rollup)?;
+    accumulator.add_collection(diff_collection);
+    let mut diff_total_collection = accumulator.finish();
+
+    if diff_total_collection.skipped_commits > 0 {
+        eprintln!(
+            "Warning: skipped {} commits that could not be read. Use collect to list them.",
+            diff_total_collection.skipped_commits
+        );
+    }
+    if diff_total_collection.skipped_submodules > 0 {
+        eprintln!(
+            "Warning: left out {} changes to submodules that could not be read. Use collect to list them.",
+            diff_total_collection.skipped_submodules
+        );
+    }
+    warn_truncated_history(&diff_total_collection.history_truncated_at);
+    points.add_points(&mut diff_total_collection)?;
+
+    print_totals(&diff_total_collection, format)
+}
+
+// Objects a partial clone left out can't be fetched while collecting, so commits touching them fail
+fn warn_partial_clone(path: &str, skip_errors: bool) -> Result<(), CliError> {
+    if !skip_errors && is_partial_clone(path)? {
+        eprintln!(
+            "Warning: {} is a partial clone. Commits with missing objects will fail; use --skip-errors to skip them.",
+            path
+        );
+    }
     Ok(())
 }
 
-fn add_points(
-    diff_total_collection: &mut DiffTotalCollection,
-    points_path: &Option<String>,
-) -> Result<(), CliError> {
-    if points_path.is_some() {
-        let str_path = points_path.to_owned().unwrap();
-        let path = Path::new(&str_path);
-        let points_collection = load_points(path)?;
-        diff_total_collection.add_points(&points_collection);
+fn warn_truncated_history(history_truncated_at: &[String]) {
+    if !history_truncated_at.is_empty() {
+        eprintln!(
+            "Warning: this is a shallow clone, history is truncated at {}",
+            history_truncated_at.join(", ")
+        );
     }
+}
+
+fn warn_skipped_submodules(diff_result: &DiffResult) {
+    for skipped_submodule in diff_result.skipped_submodules.iter() {
+        eprintln!(
+            "Warning: left out {} in commit {}",
+            skipped_submodule, diff_result.commit
+        );
+    }
+}
+
+// Config files are found from the first repo being scanned, the directory holding a workspace
+// manifest, or the current directory for total
+fn load_config(matches: &ArgMatches) -> Result<Config, CliError> {
+    let path = match matches.value_of("workspace") {
+        Some(manifest_path) => Path::new(manifest_path)
+            .parent()
+            .map(|directory| directory.to_string_lossy().to_string())
+            .filter(|directory| !directory.is_empty())
+            .unwrap_or_else(|| ".".to_string()),
+        None => matches.value_of("filepath").unwrap_or(".").to_string(),
+    };
+    Config::load(&path)
+}
 
+fn show_config_command(matches: &ArgMatches) -> Result<(), CliError> {
+    let config = load_config(matches)?;
+    print!("{}", config.effective());
     Ok(())
 }
 
-fn load_points(points_path: &Path) -> Result<StoryPointCollection, CliError> {
-    let file = File::open(&points_path)?;
-    let reader = BufReader::new(file);
-    let points_collection = serde_json::from_reader(reader)?;
-    Ok(points_collection)
+fn clear_cache_command(path: &str) -> Result<(), CliError> {
+    if clear_cache(path)? {
+        println!("Cleared the diff cache for {}", path);
+    } else {
+        println!("There is no diff cache for {}", path);
+    }
+    Ok(())
+}
+
+fn get_collect_options(args: &CollectArgs) -> CollectOptions {
+    let mut options = CollectOptions::new()
+        .branch(&args.branch)
+        .matcher(&args.matcher)
+        .jobs(args.jobs)
+        .use_cache(args.use_cache)
+        .skip_errors(args.skip_errors)
+        .shallow_boundary(args.shallow_boundary)
+        .merge_mode(args.merge_mode)
+        .reverts(args.reverts)
+        .cherry_picks(args.cherry_picks)
+        .submodules(args.submodules)
+        .exclude_bots(args.exclude_bots)
+        .authors(&args.authors)
+        .exclude_authors(&args.exclude_authors)
+        .exclude_paths(&args.exclude_paths);
+    if let Some(pattern) = &args.matcher_pattern {
+        options = options.matcher_pattern(&args.matcher, pattern);
+    }
+    if let Some(repo_name) = &args.repo_name {
+        options = options.repo_name(repo_name);
+    }
+    options
+}
+
+// clap checks a format given as an argument, but one from a config file may only suit other
+// commands, which leaves this one writing its default
+fn command_format<'a>(format: &'a str, formats: &[&str]) -> &'a str {
+    if formats.contains(&format) {
+        format
+    } else {
+        DEFAULT_FORMAT
+    }
+}
+
+fn print_totals(diff_total_collection: &DiffTotalCollection, format: &str) -> Result<(), CliError> {
+    match format {
+        "table" => print!("{}", diff_total_collection),
+        _ => {
+            let json = serde_json::to_string(diff_total_collection)?;
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
+
+// Totals stories under their canonical story numbers, and under the top of their hierarchy for
+// --rollup, which needs a hierarchy file
+fn get_accumulator(points: &PointsOptions, rollup: bool) -> Result<TotalAccumulator, CliError> {
+    let accumulator = TotalAccumulator::new().with_aliases(points.load_aliases()?);
+    if !rollup {
+        return Ok(accumulator);
+    }
+    if points.hierarchy_paths.is_empty() {
+        return Err(InputError::from(
+            "--rollup needs a hierarchy file, given with --hierarchy or in a config file.",
+        )
+        .into());
+    }
+
+    Ok(accumulator.with_hierarchy(points.load_hierarchy()?))
 }
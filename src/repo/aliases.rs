@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::repo::diff::{
+    find_conflicts, merge_entries, normalize_story_number, DiffResult, StoryConflict,
+};
+
+/// Another name for a story, e.g. its key before a project was migrated.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoryAlias {
+    pub alias: String,
+    pub story_number: String,
+}
+
+/// An alias file, shaped like `{"aliases": [{"alias": "OLD-12", "story_number": "NEW-340"}]}`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StoryAliasCollection {
+    pub aliases: Vec<StoryAlias>,
+}
+
+impl StoryAliasCollection {
+    /// Lists the aliases that appear more than once naming different stories.
+    pub fn conflicts(&self) -> Vec<StoryConflict> {
+        find_conflicts(self.aliases.iter().map(|alias| {
+            (
+                alias.alias.as_str(),
+                normalize_story_number(&alias.story_number),
+            )
+        }))
+    }
+
+    /// Merges another collection into this one. Aliases from `other` replace existing aliases
+    /// with the same name; within `other` the first entry for an alias wins.
+    pub fn merge(&mut self, other: StoryAliasCollection) -> &mut Self {
+        merge_entries(&mut self.aliases, other.aliases, |alias| &alias.alias);
+        self
+    }
+
+    /// Builds a lookup keyed by normalized alias. The first entry for an alias wins.
+    pub fn index(&self) -> StoryAliasIndex {
+        let mut aliases: HashMap<String, String> = HashMap::with_capacity(self.aliases.len());
+
+        for alias in self.aliases.iter() {
+            aliases
+                .entry(normalize_story_number(&alias.alias))
+                .or_insert_with(|| alias.story_number.to_string());
+        }
+
+        StoryAliasIndex { aliases }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct StoryAliasIndex {
+    aliases: HashMap<String, String>,
+}
+
+impl StoryAliasIndex {
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    /// The canonical story number for `story_number`, normalized, following aliases of aliases.
+    /// Every story in a cycle of aliases resolves to the lowest of them. A story with no alias is
+    /// returned as it is.
+    pub fn resolve(&self, story_number: &str) -> String {
+        let mut chain = vec![normalize_story_number(story_number)];
+
+        while let Some(canonical) = chain.last().and_then(|last| self.aliases.get(last)) {
+            let key = normalize_story_number(canonical);
+            if let Some(start) = chain.iter().position(|seen| *seen == key) {
+                return chain[start..].iter().min().cloned().unwrap_or(key);
+            }
+            chain.push(key);
+        }
+        match chain.len() {
+            1 => story_number.to_string(),
+            _ => chain.pop().unwrap_or_default(),
+        }
+    }
+
+    /// Replaces a diff result's story numbers with their canonical ones. A commit naming a story
+    /// by both its old and new numbers is counted once.
+    pub fn apply(&self, diff_result: &mut DiffResult) {
+        if self.is_empty() {
+            return;
+        }
+
+        let mut story_numbers: Vec<String> = Vec::with_capacity(diff_result.story_number.len());
+        for story_number in diff_result.story_number.iter() {
+            let canonical = self.resolve(story_number);
+            if !story_numbers.contains(&canonical) {
+                story_numbers.push(canonical);
+            }
+        }
+        diff_result.story_number = story_numbers;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias(alias: &str, story_number: &str) -> StoryAlias {
+        StoryAlias {
+            alias: alias.to_string(),
+            story_number: story_number.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolves_chains_of_aliases() {
+        let aliases = StoryAliasCollection {
+            aliases: vec![alias("OLD-12", "MID-40"), alias("mid 40", "NEW-340")],
+        };
+
+        assert_eq!(aliases.index().resolve("old-12"), "NEW-340");
+        assert_eq!(aliases.index().resolve("S-10001"), "S-10001");
+    }
+
+    #[test]
+    fn normalizes_the_stories_aliases_point_to() {
+        let aliases = StoryAliasCollection {
+            aliases: vec![alias("S-20001", "s 10001"), alias("S-20002", "S_10001")],
+        };
+        let mut diff_result = DiffResult {
+            story_number: vec!["S-20001".to_string(), "S-10001".to_string()],
+            ..DiffResult::default()
+        };
+
+        assert_eq!(aliases.index().resolve("S-20002"), "S-10001");
+        aliases.index().apply(&mut diff_result);
+        assert_eq!(diff_result.story_number, vec!["S-10001"]);
+    }
+
+    #[test]
+    fn counts_a_story_named_twice_once() {
+        let aliases = StoryAliasCollection {
+            aliases: vec![alias("OLD-12", "NEW-340"), alias("NEW-340", "OLD-12")],
+        };
+        let mut diff_result = DiffResult {
+            story_number: vec!["OLD-12".to_string(), "NEW-340".to_string()],
+            ..DiffResult::default()
+        };
+
+        aliases.index().apply(&mut diff_result);
+        assert_eq!(diff_result.story_number.len(), 1);
+    }
+
+    #[test]
+    fn keeps_the_first_alias_in_a_file_and_the_last_file() {
+        let mut aliases = StoryAliasCollection {
+            aliases: vec![
+                alias("OLD-12", "NEW-340"),
+                alias("old 12", "NEW-341"),
+                alias("OLD-13", "new 340"),
+                alias("OLD-13", "NEW-340"),
+            ],
+        };
+
+        let conflicts = aliases.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].to_string(), "OLD-12 => NEW-340 / NEW-341");
+        assert_eq!(aliases.index().resolve("OLD-12"), "NEW-340");
+
+        aliases.merge(StoryAliasCollection {
+            aliases: vec![alias("OLD-12", "NEW-342")],
+        });
+        assert_eq!(aliases.index().resolve("OLD-12"), "NEW-342");
+    }
+}
@@ -0,0 +1,81 @@
+use git2::{Commit, Signature};
+use regex::Regex;
+
+use crate::repo::CollectOptions;
+
+// GitHub apps commit as 'name[bot]'; other bots are known by name, or by an address like
+// 'ci-bot@example.com'
+const BOT_PATTERNS: &[&str] = &[
+    r"\[bot\]",
+    r"(?i)^(dependabot|renovate|greenkeeper|snyk-bot|semantic-release-bot|github-actions|release-bot)\b",
+    r"(?i)<(bot|[^>]*[-_.]bot)@",
+];
+
+/// Decides which commits are counted by who authored and committed them. Each is matched as
+/// 'Name <email>'.
+#[derive(Debug)]
+pub struct AuthorFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl AuthorFilter {
+    pub fn new(options: &CollectOptions) -> Result<AuthorFilter, regex::Error> {
+        let bot_patterns = match options.exclude_bots {
+            true => BOT_PATTERNS,
+            false => &[],
+        };
+
+        Ok(AuthorFilter {
+            include: compile(options.authors.iter().map(|author| get_pattern(author)))?,
+            exclude: compile(
+                options
+                    .exclude_authors
+                    .iter()
+                    .map(|author| get_pattern(author))
+                    .chain(bot_patterns.iter().map(|pattern| pattern.to_string())),
+            )?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// A commit is kept if its author or committer matches an included pattern, when any are
+    /// given, and neither matches an excluded one.
+    pub fn keeps(&self, commit: &Commit) -> bool {
+        let people = [
+            format_signature(&commit.author()),
+            format_signature(&commit.committer()),
+        ];
+        let matches = |patterns: &[Regex]| {
+            patterns
+                .iter()
+                .any(|pattern| people.iter().any(|person| pattern.is_match(person)))
+        };
+
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+fn compile<I: Iterator<Item = String>>(patterns: I) -> Result<Vec<Regex>, regex::Error> {
+    patterns.map(|pattern| Regex::new(&pattern)).collect()
+}
+
+// An email domain like '@example.com' matches addresses at that domain, anything else is a regex
+fn get_pattern(author: &str) -> String {
+    match author.strip_prefix('@') {
+        Some(domain) if !domain.is_empty() => format!("(?i)@{}>$", regex::escape(domain)),
+        _ => author.to_string(),
+    }
+}
+
+// e.g. 'Jane Doe <jane@example.com>'
+fn format_signature(signature: &Signature) -> String {
+    format!(
+        "{} <{}>",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes())
+    )
+}
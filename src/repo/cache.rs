@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use crate::repo::diff::DiffResult;
+
+// Bump whenever the same commit and options would produce a different DiffResult
+const CACHE_VERSION: u32 = 7;
+const CACHE_DIRECTORY: &str = "story-line-counter";
+const CACHE_FILE: &str = "diff-cache.json";
+
+/// Diff results from earlier runs, stored inside the repo's git directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DiffCache {
+    version: u32,
+    // options key -> commit key -> diff result
+    entries: HashMap<String, HashMap<String, DiffResult>>,
+}
+
+impl DiffCache {
+    /// Loads the cache for a repo. A missing, unreadable or outdated cache loads as empty.
+    pub fn load(git_dir: &Path) -> DiffCache {
+        let cache = File::open(get_cache_path(git_dir))
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, DiffCache>(BufReader::new(file)).ok());
+
+        match cache {
+            Some(cache) if cache.version == CACHE_VERSION => cache,
+            _ => DiffCache {
+                version: CACHE_VERSION,
+                entries: HashMap::new(),
+            },
+        }
+    }
+
+    pub fn save(&self, git_dir: &Path) -> Result<(), io::Error> {
+        let cache_directory = git_dir.join(CACHE_DIRECTORY);
+        fs::create_dir_all(&cache_directory)?;
+
+        // write to a uniquely named file beside the cache and rename it, so neither an
+        // interrupted run nor two runs at once can leave the cache truncated
+        let mut temp_file = NamedTempFile::new_in(&cache_directory)?;
+        {
+            let mut writer = BufWriter::new(temp_file.as_file_mut());
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+        }
+        temp_file.persist(get_cache_path(git_dir))?;
+        Ok(())
+    }
+
+    pub fn get(&self, options_key: &str, commit_key: &str) -> Option<&DiffResult> {
+        self.entries
+            .get(options_key)
+            .and_then(|diffs| diffs.get(commit_key))
+    }
+
+    pub fn insert(&mut self, options_key: &str, commit_key: String, diff_result: DiffResult) {
+        self.entries
+            .entry(options_key.to_string())
+            .or_default()
+            .insert(commit_key, diff_result);
+    }
+}
+
+/// Deletes a repo's cache. Returns false if there was nothing to delete.
+pub fn clear(git_dir: &Path) -> Result<bool, io::Error> {
+    let cache_directory = git_dir.join(CACHE_DIRECTORY);
+    if !cache_directory.exists() {
+        return Ok(false);
+    }
+
+    fs::remove_dir_all(cache_directory)?;
+    Ok(true)
+}
+
+pub fn get_cache_path(git_dir: &Path) -> PathBuf {
+    git_dir.join(CACHE_DIRECTORY).join(CACHE_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn discards_caches_from_other_versions() {
+        let git_dir = TempDir::new().unwrap();
+        let mut cache = DiffCache::load(git_dir.path());
+        cache.insert("options", "commit".to_string(), DiffResult::default());
+        cache.save(git_dir.path()).unwrap();
+        assert!(DiffCache::load(git_dir.path())
+            .get("options", "commit")
+            .is_some());
+
+        cache.version = CACHE_VERSION - 1;
+        cache.save(git_dir.path()).unwrap();
+        let cache = DiffCache::load(git_dir.path());
+
+        assert_eq!(cache.version, CACHE_VERSION);
+        assert!(cache.get("options", "commit").is_none());
+    }
+
+    #[test]
+    fn clears_only_existing_caches() {
+        let git_dir = TempDir::new().unwrap();
+        assert!(!clear(git_dir.path()).unwrap());
+
+        DiffCache::load(git_dir.path())
+            .save(git_dir.path())
+            .unwrap();
+        assert!(get_cache_path(git_dir.path()).exists());
+        assert!(clear(git_dir.path()).unwrap());
+        assert!(!get_cache_path(git_dir.path()).exists());
+    }
+}
@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+
+use crate::repo::diff::{normalize_story_number, DiffTotal, DiffTotalCollection};
+
+/// The differences between two reports, e.g. from consecutive nightly runs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TotalsComparison {
+    /// Stories only in the newer report.
+    pub new_stories: Vec<StoryChange>,
+    /// Stories only in the older report.
+    pub removed_stories: Vec<StoryChange>,
+    /// Stories in both reports whose counts or points changed.
+    pub changed_stories: Vec<StoryChange>,
+}
+
+/// How a story's totals changed from the older report to the newer one. A story missing from
+/// one of them counts as zero there.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoryChange {
+    pub story_number: String,
+    pub files_changed: i64,
+    pub insertions: i64,
+    pub deletions: i64,
+    pub total_diff_results: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_after: Option<String>,
+    /// The change in points, when both sides are numbers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_change: Option<String>,
+}
+
+impl StoryChange {
+    fn new(story_number: &str, before: Option<&DiffTotal>, after: Option<&DiffTotal>) -> Self {
+        let count = |total: Option<&DiffTotal>, get: fn(&DiffTotal) -> usize| {
+            total.map_or(0, |total| get(total) as i64)
+        };
+        let points_before = before.map(|total| total.points.to_string());
+        let points_after = after.map(|total| total.points.to_string());
+        let points_change = parse_points(points_after.as_deref())
+            .zip(parse_points(points_before.as_deref()))
+            .map(|(after, before)| format_change(after - before));
+
+        StoryChange {
+            story_number: story_number.to_string(),
+            files_changed: count(after, |total| total.files_changed)
+                - count(before, |total| total.files_changed),
+            insertions: count(after, |total| total.insertions)
+                - count(before, |total| total.insertions),
+            deletions: count(after, |total| total.deletions)
+                - count(before, |total| total.deletions),
+            total_diff_results: count(after, |total| total.total_diff_results)
+                - count(before, |total| total.total_diff_results),
+            points_before,
+            points_after,
+            points_change,
+        }
+    }
+
+    fn is_unchanged(&self) -> bool {
+        self.files_changed == 0
+            && self.insertions == 0
+            && self.deletions == 0
+            && self.total_diff_results == 0
+            && self.points_before == self.points_after
+    }
+}
+
+impl fmt::Display for StoryChange {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} :: ({:+} files, {:+} insertions, {:+} deletions) {:+} commits",
+            self.story_number,
+            self.files_changed,
+            self.insertions,
+            self.deletions,
+            self.total_diff_results
+        )?;
+
+        if self.points_before != self.points_after {
+            write!(
+                f,
+                ", {}sp -> {}sp",
+                self.points_before.as_deref().unwrap_or("0"),
+                self.points_after.as_deref().unwrap_or("0")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TotalsComparison {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let sections = [
+            ("New stories", &self.new_stories),
+            ("Removed stories", &self.removed_stories),
+            ("Changed stories", &self.changed_stories),
+        ];
+
+        for (index, (title, changes)) in sections.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{} ({}):", title, changes.len())?;
+            for change in changes.iter() {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares an older report with a newer one. Stories are matched by their normalized story
+/// numbers, and each list is sorted by story number.
+pub fn compare(older: &DiffTotalCollection, newer: &DiffTotalCollection) -> TotalsComparison {
+    let (older_totals, newer_totals) = (index_totals(older), index_totals(newer));
+    let mut comparison = TotalsComparison::default();
+
+    for (story_number, after) in newer_totals.iter() {
+        match older_totals.get(story_number) {
+            Some(before) => {
+                let change = StoryChange::new(&after.story_number, Some(before), Some(after));
+                if !change.is_unchanged() {
+                    comparison.changed_stories.push(change);
+                }
+            }
+            None => comparison.new_stories.push(StoryChange::new(
+                &after.story_number,
+                None,
+                Some(after),
+            )),
+        }
+    }
+    for (story_number, before) in older_totals.iter() {
+        if !newer_totals.contains_key(story_number) {
+            comparison.removed_stories.push(StoryChange::new(
+                &before.story_number,
+                Some(before),
+                None,
+            ));
+        }
+    }
+
+    for changes in [
+        &mut comparison.new_stories,
+        &mut comparison.removed_stories,
+        &mut comparison.changed_stories,
+    ] {
+        changes.sort_by(|a, b| a.story_number.cmp(&b.story_number));
+    }
+    comparison
+}
+
+fn index_totals(collection: &DiffTotalCollection) -> HashMap<String, &DiffTotal> {
+    collection
+        .totals
+        .values()
+        .map(|total| (normalize_story_number(&total.story_number), total))
+        .collect()
+}
+
+fn parse_points(points: Option<&str>) -> Option<f64> {
+    points.and_then(|points| points.trim().parse::<f64>().ok())
+}
+
+// e.g. '+3', '-0.5' or '0'
+fn format_change(change: f64) -> String {
+    match (change.fract() == 0.0, change > 0.0) {
+        (true, true) => format!("+{}", change as i64),
+        (true, false) => format!("{}", change as i64),
+        (false, true) => format!("+{}", change),
+        (false, false) => change.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(stories: &[(&str, &str, usize)]) -> DiffTotalCollection {
+        DiffTotalCollection {
+            totals: stories
+                .iter()
+                .map(|(story_number, points, insertions)| {
+                    let mut total = DiffTotal::new(story_number);
+                    total.points = points.to_string();
+                    total.insertions = *insertions;
+                    total.total_diff_results = 1;
+                    (story_number.to_string(), total)
+                })
+                .collect(),
+            skipped_commits: 0,
+            history_truncated_at: Vec::new(),
+            excluded_by_author: 0,
+            skipped_submodules: 0,
+            reconciliation: None,
+        }
+    }
+
+    #[test]
+    fn lists_new_removed_and_changed_stories() {
+        let older = totals(&[
+            ("S-10001", "3", 10),
+            ("S-10002", "1", 5),
+            ("D-20001", "0", 2),
+        ]);
+        let newer = totals(&[
+            ("S-10001", "5", 14),
+            ("s 10002", "1", 5),
+            ("S-10003", "2", 7),
+        ]);
+
+        let comparison = compare(&older, &newer);
+
+        let story_numbers = |changes: &[StoryChange]| -> Vec<String> {
+            changes
+                .iter()
+                .map(|change| change.story_number.to_string())
+                .collect()
+        };
+        assert_eq!(story_numbers(&comparison.new_stories), vec!["S-10003"]);
+        assert_eq!(story_numbers(&comparison.removed_stories), vec!["D-20001"]);
+        assert_eq!(story_numbers(&comparison.changed_stories), vec!["S-10001"]);
+
+        let changed = &comparison.changed_stories[0];
+        assert_eq!(changed.insertions, 4);
+        assert_eq!(changed.total_diff_results, 0);
+        assert_eq!(changed.points_change.as_deref(), Some("+2"));
+        assert_eq!(comparison.removed_stories[0].insertions, -2);
+    }
+}
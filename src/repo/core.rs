@@ -1,8 +1,11 @@
 use git2::{Branch, BranchType, Commit, Error, Oid, Repository};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 
 pub struct RepoPosition<'repo> {
     pub repository: &'repo Repository,
-    pub branch: Branch<'repo>,
     pub commit: Commit<'repo>,
 }
 
@@ -22,8 +25,7 @@ pub fn get_repo_head<'repo>(
     let head = branch.get().peel_to_commit()?;
 
     Ok(RepoPosition {
-        repository: &repository,
-        branch,
+        repository,
         commit: head,
     })
 }
@@ -35,3 +37,50 @@ pub fn get_branch<'repo>(
 ) -> Result<Branch<'repo>, Error> {
     repository.find_branch(branch, branch_type)
 }
+
+// git lists the boundary commits of a shallow clone, one id per line, in $GIT_DIR/shallow
+pub fn get_shallow_boundaries(repository: &Repository) -> Result<HashSet<Oid>, Error> {
+    if !repository.is_shallow() {
+        return Ok(HashSet::new());
+    }
+
+    let shallow = match fs::read_to_string(get_common_dir(repository).join("shallow")) {
+        Ok(shallow) => shallow,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(error) => return Err(Error::from_str(&error.to_string())),
+    };
+
+    shallow
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(Oid::from_str)
+        .collect()
+}
+
+// A linked worktree keeps the files shared with the main checkout in the directory named by
+// its `commondir` file
+fn get_common_dir(repository: &Repository) -> PathBuf {
+    let git_dir = repository.path();
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common_dir) => git_dir.join(common_dir.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+// Partial clones leave out blobs or trees and fetch them on demand, which libgit2 can't do
+pub fn is_partial_clone(repository: &Repository) -> Result<bool, Error> {
+    let config = repository.config()?;
+    if config.get_string("extensions.partialclone").is_ok() {
+        return Ok(true);
+    }
+
+    let mut promisor = false;
+    let mut entries = config.entries(Some(r"remote\..*\.promisor"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        promisor |= entry.value() == Some("true");
+    }
+
+    Ok(promisor)
+}
@@ -1,10 +1,14 @@
+use chrono::{DateTime, NaiveDate};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::fmt::Formatter;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct DiffResult {
+    /// The id of the commit, which is diffed against its first parent.
+    #[serde(default)]
+    pub commit: String,
     pub story_number: Vec<String>,
     pub points: String,
     pub first_summary: String,
@@ -12,6 +16,38 @@ pub struct DiffResult {
     pub files_changed: usize,
     pub insertions: usize,
     pub deletions: usize,
+    /// When the commit was committed, in seconds since the Unix epoch. Collections written by
+    /// older versions don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+    /// Set on the oldest commits of a shallow clone. Their parents are missing, so they are
+    /// diffed against the empty tree and count every file in the repo.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub shallow_boundary: bool,
+    /// The commit this one reverts, as named by its message: an id from `This reverts commit`,
+    /// or the quoted summary from a `Revert "..."` subject when no id is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverts: Option<String>,
+    /// The commit this one was cherry-picked from, from a `(cherry picked from commit ...)` line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cherry_picked_from: Option<String>,
+    /// The name of the repo the commit is from, set when several repos are collected together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Submodule pointer changes whose commit ranges are included in the counts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submodules: Vec<SubmoduleChange>,
+    /// Submodule pointer changes left out of the counts because the submodule couldn't be read.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_submodules: Vec<SkippedSubmodule>,
+}
+
+impl DiffResult {
+    // Whether `reference` names this commit, by a full or abbreviated id or by its summary
+    fn is_named_by(&self, reference: &str) -> bool {
+        (reference.len() >= 7 && self.commit.starts_with(reference))
+            || self.second_summary == reference
+    }
 }
 
 impl fmt::Display for DiffResult {
@@ -32,21 +68,154 @@ impl fmt::Display for DiffResult {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A commit moving a submodule from one commit to another, counted as the diff between the two.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubmoduleChange {
+    pub path: String,
+    pub from: String,
+    pub to: String,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// A submodule pointer change that couldn't be counted, e.g. because the submodule isn't
+/// initialized or is no longer listed in `.gitmodules` at that path.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SkippedSubmodule {
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedSubmodule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "submodule '{}': {}", self.path, self.reason)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct DiffCollection {
     pub diffs: Vec<DiffResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_commits: Vec<SkippedCommit>,
+    /// The shallow boundary commits the history walk stopped at, if the clone is shallow.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history_truncated_at: Vec<String>,
+    /// The number of commits left out by the author filters.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub excluded_by_author: usize,
+}
+
+impl DiffCollection {
+    /// Moves another collection's diffs, skipped commits and truncated history onto this one.
+    pub fn append(&mut self, other: DiffCollection) -> &mut Self {
+        self.diffs.extend(other.diffs);
+        self.skipped_commits.extend(other.skipped_commits);
+        self.history_truncated_at.extend(other.history_truncated_at);
+        self.excluded_by_author += other.excluded_by_author;
+        self
+    }
+
+    /// Drops reverted commits along with the reverts, so each pair nets out to nothing.
+    /// Reverts are matched newest first, so reverting a revert brings the original back.
+    /// Returns the number of commits dropped.
+    pub fn exclude_reverts(&mut self) -> usize {
+        let mut excluded: HashSet<usize> = HashSet::new();
+
+        for (index, diff_result) in self.diffs.iter().enumerate() {
+            let reverted = match &diff_result.reverts {
+                Some(reverted) if !excluded.contains(&index) => reverted,
+                _ => continue,
+            };
+
+            let original = self
+                .diffs
+                .iter()
+                .enumerate()
+                .find(|(other, original)| {
+                    *other != index && !excluded.contains(other) && original.is_named_by(reverted)
+                })
+                .map(|(other, _)| other);
+            if let Some(original) = original {
+                excluded.insert(index);
+                excluded.insert(original);
+            }
+        }
+
+        self.remove_indexes(&excluded)
+    }
+
+    /// Drops cherry-picked copies of a change that is already in the collection, either as the
+    /// original commit or as an earlier copy. Returns the number of commits dropped.
+    pub fn dedupe_cherry_picks(&mut self) -> usize {
+        let mut excluded: HashSet<usize> = HashSet::new();
+        let mut picked: HashSet<&str> = HashSet::new();
+
+        for (index, diff_result) in self.diffs.iter().enumerate() {
+            let source = match &diff_result.cherry_picked_from {
+                Some(source) => source.as_str(),
+                None => continue,
+            };
+
+            let has_original = self.diffs.iter().any(|original| {
+                original.commit != diff_result.commit && original.is_named_by(source)
+            });
+            if has_original || !picked.insert(source) {
+                excluded.insert(index);
+            }
+        }
+
+        self.remove_indexes(&excluded)
+    }
+
+    fn remove_indexes(&mut self, excluded: &HashSet<usize>) -> usize {
+        let mut index = 0;
+        self.diffs.retain(|_| {
+            index += 1;
+            !excluded.contains(&(index - 1))
+        });
+
+        excluded.len()
+    }
 }
 
 impl fmt::Display for DiffCollection {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         self.diffs.iter().for_each(|diff_result| {
-            writeln!(f, "{}", diff_result.to_string()).unwrap();
+            writeln!(f, "{}", diff_result).unwrap();
         });
 
         Ok(())
     }
 }
 
+/// One value in a stream of collect output: a whole collection as written by `collect`, or a
+/// single diff result as written by `collect --format jsonl`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum CollectionItem {
+    Collection(DiffCollection),
+    Diff(Box<DiffResult>),
+}
+
+/// A commit left out of a collection because it couldn't be read or diffed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SkippedCommit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedCommit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.commit {
+            Some(commit) => write!(f, "{}: {}", commit, self.reason)?,
+            None => write!(f, "(history walk): {}", self.reason)?,
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StoryPoint {
     pub story_number: String,
@@ -65,18 +234,198 @@ pub struct StoryPointCollection {
     pub story_points: Vec<StoryPoint>,
 }
 
+impl StoryPointCollection {
+    /// Lists the stories that appear more than once with differing point values.
+    pub fn conflicts(&self) -> Vec<StoryConflict> {
+        find_conflicts(self.story_points.iter().map(|story_point| {
+            (
+                story_point.story_number.as_str(),
+                story_point.points.to_string(),
+            )
+        }))
+    }
+
+    /// Merges another collection into this one. Entries from `other` take precedence over
+    /// entries already present; within `other` the first entry for a story wins.
+    pub fn merge(&mut self, other: StoryPointCollection) -> &mut Self {
+        merge_entries(&mut self.story_points, other.story_points, |story_point| {
+            &story_point.story_number
+        });
+        self
+    }
+
+    /// Builds a lookup keyed by normalized story number. The first entry for a story wins.
+    pub fn index(&self) -> StoryPointIndex {
+        let mut points: HashMap<String, String> = HashMap::with_capacity(self.story_points.len());
+
+        for story_point in self.story_points.iter() {
+            points
+                .entry(normalize_story_number(&story_point.story_number))
+                .or_insert_with(|| story_point.points.clone());
+        }
+
+        StoryPointIndex { points }
+    }
+}
+
 impl fmt::Display for StoryPointCollection {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         self.story_points.iter().for_each(|story_point| {
-            writeln!(f, "{}", story_point.to_string()).unwrap();
+            writeln!(f, "{}", story_point).unwrap();
         });
 
         Ok(())
     }
 }
 
+/// A story listed more than once in the same file with different values, e.g. its points or
+/// its parent.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoryConflict {
+    pub story_number: String,
+    pub values: Vec<String>,
+}
+
+impl fmt::Display for StoryConflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} => {}", self.story_number, self.values.join(" / "))?;
+        Ok(())
+    }
+}
+
+/// Lists the stories in `entries` of (story number, value) that appear more than once with
+/// differing values, in the order they first appear.
+pub fn find_conflicts<'a, I>(entries: I) -> Vec<StoryConflict>
+where
+    I: IntoIterator<Item = (&'a str, String)>,
+{
+    let mut values: HashMap<String, Vec<String>> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+
+    for (story_number, value) in entries {
+        let story_number = normalize_story_number(story_number);
+        let story_values = values.entry(story_number.clone()).or_insert_with(|| {
+            order.push(story_number);
+            Vec::new()
+        });
+        if !story_values.contains(&value) {
+            story_values.push(value);
+        }
+    }
+
+    order
+        .into_iter()
+        .filter_map(|story_number| {
+            let values = values.remove(&story_number)?;
+            Some(StoryConflict {
+                story_number,
+                values,
+            })
+            .filter(|conflict| conflict.values.len() > 1)
+        })
+        .collect()
+}
+
+/// Merges the entries of one file into those of earlier files. Entries in `other` replace
+/// existing entries for the same story; within `other` the first entry for a story wins.
+pub fn merge_entries<T>(entries: &mut Vec<T>, other: Vec<T>, story_number: fn(&T) -> &str) {
+    // where each story already is, so large files merge in one pass
+    let mut positions: HashMap<String, usize> = HashMap::with_capacity(entries.len() + other.len());
+    for (position, entry) in entries.iter().enumerate() {
+        positions
+            .entry(normalize_story_number(story_number(entry)))
+            .or_insert(position);
+    }
+
+    let mut merged: HashSet<String> = HashSet::new();
+    for entry in other {
+        let key = normalize_story_number(story_number(&entry));
+        if !merged.insert(key.clone()) {
+            continue;
+        }
+
+        match positions.get(&key) {
+            Some(&position) => entries[position] = entry,
+            None => {
+                positions.insert(key, entries.len());
+                entries.push(entry);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StoryPointIndex {
+    points: HashMap<String, String>,
+}
+
+impl StoryPointIndex {
+    pub fn get(&self, story_number: &str) -> Option<&str> {
+        self.points
+            .get(&normalize_story_number(story_number))
+            .map(|points| points.as_str())
+    }
+}
+
+pub fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+/// Writes the counts shared by every kind of total, e.g. `(3 files, ∑(10,|-2|) = 12) in 4 commits`.
+pub fn write_counts(
+    f: &mut Formatter,
+    files_changed: usize,
+    insertions: usize,
+    deletions: usize,
+    total_diff_results: usize,
+) -> fmt::Result {
+    write!(
+        f,
+        "({} files, ∑({},|-{}|) = {}) in {} commits",
+        files_changed,
+        insertions,
+        deletions,
+        insertions + deletions,
+        total_diff_results
+    )
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn format_date(timestamp: Option<i64>) -> Option<String> {
+    timestamp
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .map(|time| time.format("%Y-%m-%d").to_string())
+}
+
+/// Formats a sum of points, e.g. '5' rather than '5.0', but '2.5' as is.
+pub fn format_points(points: f64) -> String {
+    match points.fract() == 0.0 {
+        true => format!("{}", points as i64),
+        false => points.to_string(),
+    }
+}
+
+// Reduces a story number to a canonical form so 'so-123', 'SO 123' and 'SO_123' all become 'SO-123'
+pub fn normalize_story_number(story_number: &str) -> String {
+    let trimmed = story_number.trim();
+    let prefix_end = trimmed
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(trimmed.len());
+    let (prefix, rest) = trimmed.split_at(prefix_end);
+    let number = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '-' || c == '_');
+
+    if !prefix.is_empty() && !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+        format!("{}-{}", prefix.to_uppercase(), number)
+    } else {
+        trimmed.to_uppercase()
+    }
+}
+
 pub trait Pointable {
-    fn add_points(&mut self, points_collection: &StoryPointCollection) -> &mut Self;
+    fn add_points(&mut self, points_index: &StoryPointIndex) -> &mut Self;
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -87,23 +436,114 @@ pub struct DiffTotal {
     pub insertions: usize,
     pub deletions: usize,
     pub total_diff_results: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issue_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    /// The story's counts from each repo, keyed by repo name, for diffs tagged with a repo.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub repos: BTreeMap<String, RepoTotal>,
+    /// When the story's first commit was made, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_commit_at: Option<i64>,
+    /// When the story's last commit was made, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_commit_at: Option<i64>,
+    /// The number of days, in UTC, with at least one commit for the story.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub active_days: usize,
+    /// The seconds from the story's first commit to its last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycle_time_seconds: Option<i64>,
+    /// The stories rolled up into this one, keyed by story number.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub children: BTreeMap<String, DiffTotal>,
+    /// The days counted in `active_days`, kept so totals read back from JSON can go on counting.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub commit_dates: BTreeSet<NaiveDate>,
 }
 
-impl Pointable for DiffTotal {
-    fn add_points(&mut self, points_collection: &StoryPointCollection) -> &mut Self {
-        let story_number = &*self.story_number;
+impl DiffTotal {
+    pub fn new(story_number: &str) -> DiffTotal {
+        DiffTotal {
+            story_number: story_number.to_string(),
+            points: "0".to_string(),
+            files_changed: 0,
+            insertions: 0,
+            deletions: 0,
+            total_diff_results: 0,
+            title: None,
+            issue_type: None,
+            status: None,
+            repos: BTreeMap::new(),
+            first_commit_at: None,
+            last_commit_at: None,
+            active_days: 0,
+            cycle_time_seconds: None,
+            children: BTreeMap::new(),
+            commit_dates: BTreeSet::new(),
+        }
+    }
 
+    /// Adds a diff result's counts, and to its repo's counts if it's tagged with one.
+    pub fn add(&mut self, diff_result: &DiffResult) -> &mut Self {
+        self.files_changed += diff_result.files_changed;
+        self.insertions += diff_result.insertions;
+        self.deletions += diff_result.deletions;
+        self.total_diff_results += 1;
 
-        if story_number.len() > 0 {
-            let point = points_collection.story_points.iter().filter(|point| {
-                point.story_number.eq(story_number)
-            }).take(1).last();
+        if let Some(repo) = &diff_result.repo {
+            let repo_total = self.repos.entry(repo.to_string()).or_default();
+            repo_total.files_changed += diff_result.files_changed;
+            repo_total.insertions += diff_result.insertions;
+            repo_total.deletions += diff_result.deletions;
+            repo_total.total_diff_results += 1;
+        }
 
-            match point {
-                Some(point) => {
-                    self.points = point.points.clone();
-                },
-                None => ()
+        if let Some(timestamp) = diff_result.timestamp {
+            self.add_commit_time(timestamp);
+        }
+
+        self
+    }
+
+    // Collections written before commits were timestamped leave the lifecycle unset
+    fn add_commit_time(&mut self, timestamp: i64) {
+        let first_commit_at = self
+            .first_commit_at
+            .map_or(timestamp, |first| first.min(timestamp));
+        let last_commit_at = self
+            .last_commit_at
+            .map_or(timestamp, |last| last.max(timestamp));
+        self.first_commit_at = Some(first_commit_at);
+        self.last_commit_at = Some(last_commit_at);
+        self.cycle_time_seconds = Some(last_commit_at - first_commit_at);
+
+        if let Some(time) = DateTime::from_timestamp(timestamp, 0) {
+            self.commit_dates.insert(time.date_naive());
+            self.active_days = self.commit_dates.len();
+        }
+    }
+}
+
+impl Pointable for DiffTotal {
+    fn add_points(&mut self, points_index: &StoryPointIndex) -> &mut Self {
+        // a rolled up parent's points are its children's, so they aren't counted twice
+        if !self.children.is_empty() {
+            for child in self.children.values_mut() {
+                child.add_points(points_index);
+            }
+            let points: f64 = self
+                .children
+                .values()
+                .filter_map(|child| child.points.trim().parse::<f64>().ok())
+                .sum();
+            self.points = format_points(points);
+        } else if !self.story_number.is_empty() {
+            if let Some(points) = points_index.get(&self.story_number) {
+                self.points = points.to_string();
             }
         }
 
@@ -113,42 +553,404 @@ impl Pointable for DiffTotal {
 
 impl fmt::Display for DiffTotal {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(
+        write!(f, "{}", self.story_number)?;
+        if let Some(title) = &self.title {
+            write!(f, " {}", title)?;
+        }
+
+        // e.g. 'SO-123 Login page (Story, Done, 5sp)', or 'SO-123 (5)' without metadata
+        if self.title.is_none() && self.issue_type.is_none() && self.status.is_none() {
+            write!(f, " ({})", self.points)?;
+        } else {
+            let details: Vec<String> = vec![self.issue_type.clone(), self.status.clone()]
+                .into_iter()
+                .flatten()
+                .chain(Some(format!("{}sp", self.points)))
+                .collect();
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        write!(f, " :: ")?;
+        write_counts(
             f,
-            "{} ({}) :: ({} files, ∑({},|-{}|) = {}) in {} commits",
-            self.story_number,
-            self.points,
             self.files_changed,
             self.insertions,
             self.deletions,
-            self.insertions + self.deletions,
-            self.total_diff_results
+            self.total_diff_results,
         )?;
+
+        // e.g. ', 3 active days over 12.5 days (2024-03-04 to 2024-03-16)'
+        if let (Some(first), Some(last), Some(cycle_time)) = (
+            format_date(self.first_commit_at),
+            format_date(self.last_commit_at),
+            self.cycle_time_seconds,
+        ) {
+            write!(
+                f,
+                ", {} active days over {:.1} days ({} to {})",
+                self.active_days,
+                cycle_time as f64 / 86_400.0,
+                first,
+                last
+            )?;
+        }
+
+        for (repo, repo_total) in self.repos.iter() {
+            write!(f, "\n    {} :: {}", repo, repo_total)?;
+        }
+
+        // each child is indented under its parent, with its own breakdown further in
+        for child in self.children.values() {
+            write!(f, "\n    {}", child.to_string().replace('\n', "\n    "))?;
+        }
         Ok(())
     }
 }
 
+/// A story's counts from a single repo.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RepoTotal {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub total_diff_results: usize,
+}
+
+impl fmt::Display for RepoTotal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_counts(
+            f,
+            self.files_changed,
+            self.insertions,
+            self.deletions,
+            self.total_diff_results,
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DiffTotalCollection {
     pub totals: HashMap<String, DiffTotal>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skipped_commits: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history_truncated_at: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub excluded_by_author: usize,
+    /// The number of submodule pointer changes left out because the submodule couldn't be read.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub skipped_submodules: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reconciliation: Option<Reconciliation>,
+}
+
+impl DiffTotalCollection {
+    /// Every story's total, including the children of rolled up parents.
+    pub fn story_totals(&self) -> impl Iterator<Item = &DiffTotal> {
+        self.totals
+            .values()
+            .flat_map(|total| std::iter::once(total).chain(total.children.values()))
+    }
+
+    /// Compares the totals against a points collection in both directions. A rolled up parent
+    /// takes its points from its children, so only they are listed as missing points.
+    pub fn reconcile(&self, points_collection: &StoryPointCollection) -> Reconciliation {
+        let committed: HashSet<String> = self
+            .story_totals()
+            .map(|total| normalize_story_number(&total.story_number))
+            .collect();
+        let points_index = points_collection.index();
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut uncommitted_stories: Vec<StoryPoint> = points_collection
+            .story_points
+            .iter()
+            .filter(|story_point| {
+                let story_number = normalize_story_number(&story_point.story_number);
+                !committed.contains(&story_number) && seen.insert(story_number)
+            })
+            .map(|story_point| StoryPoint {
+                story_number: story_point.story_number.clone(),
+                points: points_index
+                    .get(&story_point.story_number)
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+            .collect();
+        uncommitted_stories.sort_by(|a, b| a.story_number.cmp(&b.story_number));
+
+        let mut unpointed_stories: Vec<String> = self
+            .story_totals()
+            .filter(|total| total.children.is_empty())
+            .filter(|total| points_index.get(&total.story_number).is_none())
+            .map(|total| total.story_number.to_string())
+            .collect();
+        unpointed_stories.sort();
+
+        Reconciliation {
+            uncommitted_stories,
+            unpointed_stories,
+        }
+    }
 }
 
 impl fmt::Display for DiffTotalCollection {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        self.totals.iter().for_each(|(_, diff_total)| {
-            writeln!(f, "{}", diff_total.to_string()).unwrap();
-        });
+        let mut story_numbers: Vec<&String> = self.totals.keys().collect();
+        story_numbers.sort();
+
+        for story_number in story_numbers {
+            writeln!(f, "{}", self.totals[story_number])?;
+        }
+
+        if self.skipped_commits > 0 {
+            writeln!(f)?;
+            writeln!(f, "Skipped {} unreadable commits.", self.skipped_commits)?;
+        }
+
+        if self.skipped_submodules > 0 {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Left out {} changes to submodules that could not be read.",
+                self.skipped_submodules
+            )?;
+        }
+
+        if self.excluded_by_author > 0 {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Excluded {} commits by their author or committer.",
+                self.excluded_by_author
+            )?;
+        }
+
+        if !self.history_truncated_at.is_empty() {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "History is truncated at {}; older commits are not counted.",
+                self.history_truncated_at.join(", ")
+            )?;
+        }
+
+        if let Some(reconciliation) = &self.reconciliation {
+            write!(f, "{}", reconciliation)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Stories present on only one side of a points merge.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reconciliation {
+    /// Stories in the points files with no matching commits.
+    pub uncommitted_stories: Vec<StoryPoint>,
+    /// Stories with commits but no entry in the points files.
+    pub unpointed_stories: Vec<String>,
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Pointed stories with no commits ({}):",
+            self.uncommitted_stories.len()
+        )?;
+        for story_point in self.uncommitted_stories.iter() {
+            writeln!(
+                f,
+                "  {} ({}sp)",
+                story_point.story_number, story_point.points
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Committed stories with no points ({}):",
+            self.unpointed_stories.len()
+        )?;
+        for story_number in self.unpointed_stories.iter() {
+            writeln!(f, "  {}", story_number)?;
+        }
 
         Ok(())
     }
 }
 
 impl Pointable for DiffTotalCollection {
-    fn add_points(&mut self, points_collection: &StoryPointCollection) -> &mut Self {
-        for (_, total) in &mut self.totals {
-            total.add_points(points_collection);
+    fn add_points(&mut self, points_index: &StoryPointIndex) -> &mut Self {
+        for total in self.totals.values_mut() {
+            total.add_points(points_index);
         }
 
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::TotalAccumulator;
+
+    fn story_points(points: &[(&str, &str)]) -> StoryPointCollection {
+        StoryPointCollection {
+            story_points: points
+                .iter()
+                .map(|(story_number, points)| StoryPoint {
+                    story_number: story_number.to_string(),
+                    points: points.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn normalizes_story_numbers() {
+        for story_number in ["s 10001", "S-10001", "s_10001", " s -10001 "] {
+            assert_eq!(normalize_story_number(story_number), "S-10001");
+        }
+        assert_eq!(normalize_story_number("so-123"), "SO-123");
+        assert_eq!(normalize_story_number("not a story"), "NOT A STORY");
+    }
+
+    #[test]
+    fn later_points_files_override_earlier_ones() {
+        let mut merged = story_points(&[("S-10001", "3"), ("S-10002", "1")]);
+        merged.merge(story_points(&[("s 10001", "5"), ("S-10003", "2")]));
+        merged.merge(story_points(&[("S-10003", "8"), ("s_10003", "13")]));
+
+        let index = merged.index();
+        assert_eq!(merged.story_points.len(), 3);
+        assert_eq!(index.get("S-10001"), Some("5"));
+        assert_eq!(index.get("S-10002"), Some("1"));
+        // within a file the first entry for a story wins
+        assert_eq!(index.get("S-10003"), Some("8"));
+    }
+
+    #[test]
+    fn finds_stories_listed_with_different_points() {
+        let collection = story_points(&[
+            ("S-10001", "3"),
+            ("S-10002", "1"),
+            ("s 10001", "5"),
+            ("S-10002", "1"),
+            ("s_10001", "3"),
+            ("S-10003", "2"),
+        ]);
+
+        let conflicts = collection.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].story_number, "S-10001");
+        assert_eq!(conflicts[0].values, vec!["3", "5"]);
+        assert_eq!(conflicts[0].to_string(), "S-10001 => 3 / 5");
+    }
+
+    #[test]
+    fn does_not_report_conflicts_across_files() {
+        let mut merged = story_points(&[("S-10001", "3")]);
+        merged.merge(story_points(&[("S-10001", "5")]));
+
+        assert!(merged.conflicts().is_empty());
+        assert_eq!(merged.index().get("S-10001"), Some("5"));
+    }
+
+    #[test]
+    fn shows_whichever_metadata_a_story_has() {
+        let mut total = DiffTotal::new("SO-123");
+        total.points = "5".to_string();
+        let summary = |total: &DiffTotal| total.to_string().split(" :: ").next().map(String::from);
+
+        assert_eq!(summary(&total).as_deref(), Some("SO-123 (5)"));
+        total.status = Some("Done".to_string());
+        assert_eq!(summary(&total).as_deref(), Some("SO-123 (Done, 5sp)"));
+        total.issue_type = Some("Story".to_string());
+        total.title = Some("Login page".to_string());
+        assert_eq!(
+            summary(&total).as_deref(),
+            Some("SO-123 Login page (Story, Done, 5sp)")
+        );
+    }
+
+    #[test]
+    fn index_keeps_the_first_entry_for_a_story() {
+        let index = story_points(&[("S-10001", "3"), ("s 10001", "5")]).index();
+
+        assert_eq!(index.get("s_10001"), Some("3"));
+        assert_eq!(index.get("S-10002"), None);
+    }
+
+    #[test]
+    fn keeps_counting_active_days_after_a_round_trip() {
+        const DAY: i64 = 86_400;
+        let commit_at = |timestamp: i64| DiffResult {
+            story_number: vec!["S-10001".to_string()],
+            timestamp: Some(timestamp),
+            ..DiffResult::default()
+        };
+        let mut total = DiffTotal::new("S-10001");
+        total.add(&commit_at(3 * DAY)).add(&commit_at(4 * DAY));
+
+        let json = serde_json::to_string(&total).unwrap();
+        assert!(json.contains(r#""commit_dates":["1970-01-04","1970-01-05"]"#));
+        let mut total: DiffTotal = serde_json::from_str(&json).unwrap();
+        total.add(&commit_at(4 * DAY + 60)).add(&commit_at(9 * DAY));
+
+        assert_eq!(total.active_days, 3);
+        assert!(!serde_json::to_string(&DiffTotal::new("S-10002"))
+            .unwrap()
+            .contains("commit_dates"));
+    }
+
+    fn committed(story_numbers: &[&str]) -> DiffTotalCollection {
+        let mut accumulator = TotalAccumulator::new();
+        for story_number in story_numbers {
+            accumulator.add(&DiffResult {
+                story_number: vec![story_number.to_string()],
+                insertions: 1,
+                ..DiffResult::default()
+            });
+        }
+        accumulator.finish()
+    }
+
+    #[test]
+    fn reconciles_points_against_commits() {
+        let diff_total_collection = committed(&["S-10001", "S-10002", "D-20001"]);
+        let points_collection = story_points(&[
+            ("s 10001", "3"),
+            ("S-10003", "2"),
+            ("s_10003", "5"),
+            ("S-10004", "1"),
+        ]);
+
+        let reconciliation = diff_total_collection.reconcile(&points_collection);
+
+        let uncommitted: Vec<(&str, &str)> = reconciliation
+            .uncommitted_stories
+            .iter()
+            .map(|story_point| {
+                (
+                    story_point.story_number.as_str(),
+                    story_point.points.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(uncommitted, vec![("S-10003", "2"), ("S-10004", "1")]);
+        assert_eq!(reconciliation.unpointed_stories, vec!["D-20001", "S-10002"]);
+    }
+
+    #[test]
+    fn reconciles_nothing_when_every_story_matches() {
+        let diff_total_collection = committed(&["S-10001"]);
+
+        let reconciliation = diff_total_collection.reconcile(&story_points(&[("S 10001", "3")]));
+
+        assert!(reconciliation.uncommitted_stories.is_empty());
+        assert!(reconciliation.unpointed_stories.is_empty());
+    }
+}
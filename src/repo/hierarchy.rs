@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::repo::diff::{
+    find_conflicts, merge_entries, normalize_story_number, DiffResult, DiffTotal, StoryConflict,
+};
+
+/// A story's parent, e.g. the feature or epic it belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoryParent {
+    pub story_number: String,
+    pub parent: String,
+}
+
+/// A hierarchy file, shaped like the points file:
+/// `{"story_parents": [{"story_number": "S-10001", "parent": "E-40001"}]}`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StoryHierarchy {
+    pub story_parents: Vec<StoryParent>,
+}
+
+impl StoryHierarchy {
+    /// Lists the stories that appear more than once with different parents.
+    pub fn conflicts(&self) -> Vec<StoryConflict> {
+        find_conflicts(self.story_parents.iter().map(|story_parent| {
+            (
+                story_parent.story_number.as_str(),
+                normalize_story_number(&story_parent.parent),
+            )
+        }))
+    }
+
+    /// Merges another hierarchy into this one. Parents from `other` replace existing parents of
+    /// the same story; within `other` the first entry for a story wins.
+    pub fn merge(&mut self, other: StoryHierarchy) -> &mut Self {
+        merge_entries(
+            &mut self.story_parents,
+            other.story_parents,
+            |story_parent| &story_parent.story_number,
+        );
+        self
+    }
+
+    /// Builds a lookup keyed by normalized story number. The first entry for a story wins.
+    pub fn index(&self) -> StoryParentIndex {
+        let mut parents: HashMap<String, String> = HashMap::with_capacity(self.story_parents.len());
+        for story_parent in self.story_parents.iter() {
+            parents
+                .entry(normalize_story_number(&story_parent.story_number))
+                .or_insert_with(|| story_parent.parent.to_string());
+        }
+
+        let mut index = StoryParentIndex {
+            parents,
+            tops: HashSet::new(),
+        };
+        index.tops = index
+            .parents
+            .keys()
+            .filter_map(|story_number| index.root(story_number))
+            .collect();
+        index
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StoryParentIndex {
+    parents: HashMap<String, String>,
+    // the stories at the top of each hierarchy
+    tops: HashSet<String>,
+}
+
+impl StoryParentIndex {
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// The top of the hierarchy above `story_number`, following parents of parents, or None for a
+    /// story at the top. The top of a cycle of parents is the lowest story in it.
+    pub fn root(&self, story_number: &str) -> Option<String> {
+        let mut chain = vec![normalize_story_number(story_number)];
+
+        while let Some(parent) = self.parents.get(chain.last()?) {
+            let key = normalize_story_number(parent);
+            if let Some(start) = chain.iter().position(|seen| *seen == key) {
+                let root = chain[start..].iter().min().cloned()?;
+                return Some(root).filter(|root| *root != chain[0]);
+            }
+            chain.push(key);
+        }
+        match chain.len() {
+            1 => None,
+            _ => chain.pop(),
+        }
+    }
+
+    fn is_top(&self, story_number: &str) -> bool {
+        self.tops.contains(&normalize_story_number(story_number))
+    }
+}
+
+/// Totals each commit once under the top of the hierarchy of every story it names, so a commit
+/// naming both a story and its epic isn't counted twice.
+#[derive(Debug, Default)]
+pub struct Rollup {
+    parents: StoryParentIndex,
+    parent_totals: HashMap<String, DiffTotal>,
+}
+
+impl Rollup {
+    pub fn new(parents: StoryParentIndex) -> Rollup {
+        Rollup {
+            parents,
+            parent_totals: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, diff_result: &DiffResult) {
+        if self.parents.is_empty() {
+            return;
+        }
+
+        let mut tops: Vec<String> = Vec::new();
+        for story_number in diff_result.story_number.iter() {
+            let top = match self.parents.root(story_number) {
+                Some(root) => root,
+                None if self.parents.is_top(story_number) => normalize_story_number(story_number),
+                None => continue,
+            };
+            if !tops.contains(&top) {
+                tops.push(top);
+            }
+        }
+
+        for top in tops {
+            self.parent_totals
+                .entry(top.to_string())
+                .or_insert_with(|| DiffTotal::new(&top))
+                .add(diff_result);
+        }
+    }
+
+    /// Replaces the totals of stories below a parent with the parent's total, which lists them as
+    /// its children. A parent's counts include commits naming the parent itself. Stories without
+    /// a parent are left as they are.
+    pub fn finish(self, totals: HashMap<String, DiffTotal>) -> HashMap<String, DiffTotal> {
+        let Rollup {
+            parents,
+            mut parent_totals,
+        } = self;
+        if parents.is_empty() {
+            return totals;
+        }
+        let mut rolled_up: HashMap<String, DiffTotal> = HashMap::with_capacity(totals.len());
+
+        for (story_number, total) in totals {
+            match parents.root(&story_number) {
+                Some(root) => {
+                    parent_totals
+                        .entry(root.to_string())
+                        .or_insert_with(|| DiffTotal::new(&root))
+                        .children
+                        .insert(story_number, total);
+                }
+                // the parent's own commits are already in its rolled up total
+                None if parent_totals.contains_key(&normalize_story_number(&story_number)) => {}
+                None => {
+                    rolled_up.insert(story_number, total);
+                }
+            }
+        }
+
+        rolled_up.extend(parent_totals);
+        rolled_up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::diff::{DiffTotalCollection, Pointable, StoryPoint, StoryPointCollection};
+    use crate::repo::TotalAccumulator;
+
+    fn story_parent(story_number: &str, parent: &str) -> StoryParent {
+        StoryParent {
+            story_number: story_number.to_string(),
+            parent: parent.to_string(),
+        }
+    }
+
+    #[test]
+    fn finds_the_top_of_a_hierarchy() {
+        let hierarchy = StoryHierarchy {
+            story_parents: vec![
+                story_parent("s-10001", "F-50001"),
+                story_parent("F-50001", "E-40001"),
+                story_parent("E-40001", "F-50001"),
+            ],
+        };
+        let parents = hierarchy.index();
+
+        assert_eq!(parents.root("S 10001").as_deref(), Some("E-40001"));
+        assert_eq!(parents.root("F-50001").as_deref(), Some("E-40001"));
+        assert_eq!(parents.root("E-40001"), None);
+        assert_eq!(parents.root("S-10002"), None);
+    }
+
+    #[test]
+    fn keeps_the_first_parent_in_a_file_and_the_last_file() {
+        let mut hierarchy = StoryHierarchy {
+            story_parents: vec![
+                story_parent("S-10001", "E-40001"),
+                story_parent("s 10001", "E-40002"),
+            ],
+        };
+
+        assert_eq!(
+            hierarchy.conflicts()[0].to_string(),
+            "S-10001 => E-40001 / E-40002"
+        );
+        assert_eq!(
+            hierarchy.index().root("S-10001").as_deref(),
+            Some("E-40001")
+        );
+
+        hierarchy.merge(StoryHierarchy {
+            story_parents: vec![story_parent("S-10001", "E-40003")],
+        });
+        assert_eq!(
+            hierarchy.index().root("S-10001").as_deref(),
+            Some("E-40003")
+        );
+    }
+
+    fn rolled_up(diffs: &[(&[&str], usize)], points: &[(&str, &str)]) -> DiffTotalCollection {
+        let hierarchy = StoryHierarchy {
+            story_parents: vec![
+                story_parent("S-10001", "E-40001"),
+                story_parent("S-10002", "e 40001"),
+            ],
+        };
+        let mut accumulator = TotalAccumulator::new().with_hierarchy(hierarchy.index());
+        for (story_numbers, insertions) in diffs {
+            accumulator.add(&DiffResult {
+                story_number: story_numbers.iter().map(|s| s.to_string()).collect(),
+                insertions: *insertions,
+                ..DiffResult::default()
+            });
+        }
+
+        let mut diff_total_collection = accumulator.finish();
+        let story_points = points
+            .iter()
+            .map(|(story_number, points)| StoryPoint {
+                story_number: story_number.to_string(),
+                points: points.to_string(),
+            })
+            .collect();
+        diff_total_collection.add_points(&StoryPointCollection { story_points }.index());
+        diff_total_collection
+    }
+
+    #[test]
+    fn sums_children_into_their_parent() {
+        let diff_total_collection = rolled_up(
+            &[
+                (&["S-10001"], 10),
+                (&["S-10002"], 5),
+                (&["E-40001"], 2),
+                (&["D-20001"], 1),
+            ],
+            &[
+                ("S-10001", "3"),
+                ("S-10002", "1.5"),
+                ("E-40001", "8"),
+                ("D-20001", "2"),
+            ],
+        );
+
+        let totals = &diff_total_collection.totals;
+        assert_eq!(totals.len(), 2);
+        let epic = &totals["E-40001"];
+        assert_eq!(epic.points, "4.5");
+        assert_eq!(epic.insertions, 17);
+        assert_eq!(epic.total_diff_results, 3);
+        assert_eq!(epic.children["S-10001"].points, "3");
+        assert_eq!(epic.children.len(), 2);
+        assert_eq!(totals["D-20001"].points, "2");
+    }
+
+    #[test]
+    fn counts_a_commit_naming_a_story_and_its_epic_once() {
+        let diff_total_collection = rolled_up(
+            &[(&["S-10001", "E-40001"], 10), (&["S-10001", "S-10002"], 4)],
+            &[],
+        );
+
+        let epic = &diff_total_collection.totals["E-40001"];
+        assert_eq!(epic.insertions, 14);
+        assert_eq!(epic.total_diff_results, 2);
+        assert_eq!(epic.children["S-10001"].total_diff_results, 2);
+        assert_eq!(epic.children["S-10002"].total_diff_results, 1);
+    }
+}
@@ -0,0 +1,469 @@
+use serde::de::DeserializeOwned;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::errors::{CliError, FileError};
+use crate::repo::aliases::{StoryAliasCollection, StoryAliasIndex};
+use crate::repo::diff::{
+    CollectionItem, DiffCollection, DiffTotalCollection, Pointable, StoryConflict,
+    StoryPointCollection, StoryPointIndex,
+};
+use crate::repo::filter_diffs;
+use crate::repo::hierarchy::{StoryHierarchy, StoryParentIndex};
+use crate::repo::metadata::{Describable, StoryMetadataCollection};
+use crate::repo::options::{CherryPicks, Reverts};
+#[cfg(feature = "tracker")]
+use crate::tracker::{get_client, TrackerConfig};
+
+/// The input path that stands for stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// The files points, story metadata, aliases and hierarchies are loaded from, and how they're
+/// merged onto totals. Files of each kind are merged in order, so a story in a later file
+/// overrides an earlier one; within a file the first entry for a story wins, and any others are
+/// reported.
+///
+/// ```no_run
+/// use story_line_counter::{run, CollectOptions, PointsOptions};
+///
+/// let points = PointsOptions::new().points_path("points.json").reconcile(true);
+/// let mut totals = run("/path/to/repo", &CollectOptions::new())?;
+/// points.add_points(&mut totals)?;
+/// # Ok::<(), story_line_counter::errors::CliError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct PointsOptions {
+    pub points_paths: Vec<String>,
+    pub metadata_paths: Vec<String>,
+    pub alias_paths: Vec<String>,
+    pub hierarchy_paths: Vec<String>,
+    /// The tracker to fetch points from, which points files override.
+    #[cfg(feature = "tracker")]
+    pub tracker: Option<TrackerConfig>,
+    /// Fail instead of warning when a file lists a story with conflicting values.
+    pub strict_points: bool,
+    /// Also list pointed stories with no commits and committed stories with no points.
+    pub reconcile: bool,
+}
+
+impl PointsOptions {
+    pub fn new() -> PointsOptions {
+        PointsOptions::default()
+    }
+
+    pub fn points_path(mut self, path: &str) -> Self {
+        self.points_paths.push(path.to_string());
+        self
+    }
+
+    pub fn points_paths(mut self, paths: &[String]) -> Self {
+        self.points_paths.extend_from_slice(paths);
+        self
+    }
+
+    pub fn metadata_path(mut self, path: &str) -> Self {
+        self.metadata_paths.push(path.to_string());
+        self
+    }
+
+    pub fn metadata_paths(mut self, paths: &[String]) -> Self {
+        self.metadata_paths.extend_from_slice(paths);
+        self
+    }
+
+    pub fn alias_path(mut self, path: &str) -> Self {
+        self.alias_paths.push(path.to_string());
+        self
+    }
+
+    pub fn alias_paths(mut self, paths: &[String]) -> Self {
+        self.alias_paths.extend_from_slice(paths);
+        self
+    }
+
+    pub fn hierarchy_path(mut self, path: &str) -> Self {
+        self.hierarchy_paths.push(path.to_string());
+        self
+    }
+
+    pub fn hierarchy_paths(mut self, paths: &[String]) -> Self {
+        self.hierarchy_paths.extend_from_slice(paths);
+        self
+    }
+
+    #[cfg(feature = "tracker")]
+    pub fn tracker(mut self, tracker: Option<TrackerConfig>) -> Self {
+        self.tracker = tracker;
+        self
+    }
+
+    pub fn strict_points(mut self, strict_points: bool) -> Self {
+        self.strict_points = strict_points;
+        self
+    }
+
+    pub fn reconcile(mut self, reconcile: bool) -> Self {
+        self.reconcile = reconcile;
+        self
+    }
+
+    /// Merges the points files.
+    pub fn load_points(&self) -> Result<StoryPointCollection, CliError> {
+        let mut merged = StoryPointCollection {
+            story_points: Vec::new(),
+        };
+
+        for str_path in self.points_paths.iter() {
+            let points_collection: StoryPointCollection =
+                load_json(str_path, CliError::BadPointsFile)?;
+            self.check_conflicts(
+                str_path,
+                "lists stories with conflicting points",
+                points_collection.conflicts(),
+            )?;
+            merged.merge(points_collection);
+        }
+
+        Ok(merged)
+    }
+
+    /// Merges the story metadata exports, which are JSON, or CSV for a `.csv` file.
+    pub fn load_metadata(&self) -> Result<StoryMetadataCollection, CliError> {
+        let mut merged = StoryMetadataCollection {
+            stories: Vec::new(),
+        };
+
+        for str_path in self.metadata_paths.iter() {
+            merged.merge(load_metadata_file(str_path)?);
+        }
+
+        Ok(merged)
+    }
+
+    /// Merges the alias files into a lookup of canonical story numbers.
+    pub fn load_aliases(&self) -> Result<StoryAliasIndex, CliError> {
+        let mut merged = StoryAliasCollection::default();
+
+        for str_path in self.alias_paths.iter() {
+            let alias_collection: StoryAliasCollection =
+                load_json(str_path, CliError::BadPointsFile)?;
+            self.check_conflicts(
+                str_path,
+                "lists aliases of more than one story",
+                alias_collection.conflicts(),
+            )?;
+            merged.merge(alias_collection);
+        }
+
+        Ok(merged.index())
+    }
+
+    /// Merges the hierarchy files into a lookup of each story's parents.
+    pub fn load_hierarchy(&self) -> Result<StoryParentIndex, CliError> {
+        let mut merged = StoryHierarchy::default();
+
+        for str_path in self.hierarchy_paths.iter() {
+            let hierarchy: StoryHierarchy = load_json(str_path, CliError::BadPointsFile)?;
+            self.check_conflicts(
+                str_path,
+                "lists stories with conflicting parents",
+                hierarchy.conflicts(),
+            )?;
+            merged.merge(hierarchy);
+        }
+
+        Ok(merged.index())
+    }
+
+    /// Merges story metadata and points onto totals, and reconciles them if asked to. Points
+    /// from metadata exports are overridden by the tracker, which is overridden by points files.
+    /// Returns the merged points, for anything else that should carry them.
+    pub fn add_points(
+        &self,
+        diff_total_collection: &mut DiffTotalCollection,
+    ) -> Result<StoryPointIndex, CliError> {
+        let metadata_collection = self.load_metadata()?;
+        diff_total_collection.add_metadata(&metadata_collection.index());
+
+        let mut points_collection = metadata_collection.story_points();
+        #[cfg(feature = "tracker")]
+        if let Some(tracker) = &self.tracker {
+            points_collection.merge(fetch_points(diff_total_collection, tracker)?);
+        }
+        points_collection.merge(self.load_points()?);
+
+        if !points_collection.story_points.is_empty() {
+            diff_total_collection.add_points(&points_collection.index());
+        }
+
+        if self.reconcile {
+            diff_total_collection.reconciliation =
+                Some(diff_total_collection.reconcile(&points_collection));
+        }
+
+        Ok(points_collection.index())
+    }
+
+    // The first entry for a story in a file is the one used, so any others are reported
+    fn check_conflicts(
+        &self,
+        str_path: &str,
+        description: &str,
+        conflicts: Vec<StoryConflict>,
+    ) -> Result<(), CliError> {
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        let description = format!(
+            "{}: {}",
+            description,
+            conflicts
+                .iter()
+                .map(|conflict| conflict.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        if self.strict_points {
+            return Err(CliError::BadPointsFile(FileError::new(
+                str_path,
+                description,
+            )));
+        }
+        eprintln!("Warning: {}: {}", str_path, description);
+        Ok(())
+    }
+}
+
+/// Reads a JSON file, reporting a missing or malformed file as `variant`, e.g.
+/// `CliError::BadPointsFile`.
+pub fn load_json<T: DeserializeOwned>(
+    str_path: &str,
+    variant: fn(FileError) -> CliError,
+) -> Result<T, CliError> {
+    let file = File::open(str_path).map_err(|error| variant(FileError::new(str_path, error)))?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|error| variant(FileError::json(str_path, error)))
+}
+
+fn load_metadata_file(metadata_path: &str) -> Result<StoryMetadataCollection, CliError> {
+    let bad_file = CliError::BadMetadataFile;
+    let is_csv = Path::new(metadata_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    if !is_csv {
+        return load_json(metadata_path, bad_file);
+    }
+
+    let file = File::open(metadata_path)
+        .map_err(|error| bad_file(FileError::new(metadata_path, error)))?;
+    StoryMetadataCollection::from_csv(BufReader::new(file))
+        .map_err(|error| bad_file(FileError::csv(metadata_path, error)))
+}
+
+// A tracker that can't be reached only costs the report its points, so failures are warnings
+#[cfg(feature = "tracker")]
+fn fetch_points(
+    diff_total_collection: &DiffTotalCollection,
+    tracker: &TrackerConfig,
+) -> Result<StoryPointCollection, CliError> {
+    let client = get_client(tracker)?;
+
+    let mut story_numbers: Vec<String> = diff_total_collection
+        .story_totals()
+        .map(|total| total.story_number.to_string())
+        .collect();
+    story_numbers.sort();
+
+    match client.fetch_points(&story_numbers) {
+        Ok(points_collection) => Ok(points_collection),
+        Err(error) => {
+            eprintln!(
+                "Warning: could not fetch points from {}: {}",
+                tracker.kind, error
+            );
+            Ok(StoryPointCollection {
+                story_points: Vec::new(),
+            })
+        }
+    }
+}
+
+/// Hands the diffs of every input to `add`, a collection at a time. Reverts and cherry-picks are
+/// matched across every input, so filtering needs all the diffs at once; otherwise each diff is
+/// handed over as it's read.
+pub fn read_inputs<F>(
+    paths: &[String],
+    reverts: Reverts,
+    cherry_picks: CherryPicks,
+    mut add: F,
+) -> Result<(), CliError>
+where
+    F: FnMut(DiffCollection),
+{
+    let filtering = reverts == Reverts::Exclude || cherry_picks == CherryPicks::Dedupe;
+    let mut combined = DiffCollection::default();
+
+    for path in paths {
+        for item in read_collection_items(path)? {
+            let diff_collection = match item? {
+                CollectionItem::Diff(diff_result) => DiffCollection {
+                    diffs: vec![*diff_result],
+                    ..DiffCollection::default()
+                },
+                CollectionItem::Collection(collection) => collection,
+            };
+            match filtering {
+                true => {
+                    combined.append(diff_collection);
+                }
+                false => add(diff_collection),
+            }
+        }
+    }
+
+    if filtering {
+        // A change picked onto several branches is only seen twice once their collections are combined
+        filter_diffs(&mut combined, reverts, cherry_picks);
+        add(combined);
+    }
+    Ok(())
+}
+
+/// Reads a file of collect output, or stdin for [`STDIN_PATH`]: a DiffCollection, several
+/// concatenated, or JSON Lines of diffs.
+pub fn read_collection_items(
+    path: &str,
+) -> Result<impl Iterator<Item = Result<CollectionItem, CliError>>, CliError> {
+    let reader: Box<dyn Read> = if path == STDIN_PATH {
+        Box::new(io::stdin())
+    } else {
+        let file = File::open(path)
+            .map_err(|error| CliError::BadInputFile(FileError::new(path, error)))?;
+        Box::new(file)
+    };
+    let path = match path {
+        STDIN_PATH => "stdin".to_string(),
+        path => path.to_string(),
+    };
+
+    Ok(
+        serde_json::Deserializer::from_reader(BufReader::new(reader))
+            .into_iter::<CollectionItem>()
+            .map(move |item| {
+                item.map_err(|error| CliError::BadInputFile(FileError::json(&path, error)))
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::diff::DiffResult;
+    use crate::repo::TotalAccumulator;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn write_file(dir: &TempDir, name: &str, contents: &str) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn points_files_override_metadata_and_earlier_files() {
+        let dir = TempDir::new().unwrap();
+        let metadata = write_file(
+            &dir,
+            "stories.csv",
+            "story_number,title,points\nS-10001,Login,1\nS-10002,Logout,2\n",
+        );
+        let sprint = write_file(
+            &dir,
+            "sprint.json",
+            r#"{"story_points": [{"story_number": "S-10001", "points": "3"}]}"#,
+        );
+        let fixes = write_file(
+            &dir,
+            "fixes.json",
+            r#"{"story_points": [{"story_number": "s 10001", "points": "5"}]}"#,
+        );
+        let mut accumulator = TotalAccumulator::new();
+        accumulator.add(&DiffResult {
+            story_number: vec!["S-10001".to_string(), "S-10002".to_string()],
+            ..DiffResult::default()
+        });
+        let mut diff_total_collection = accumulator.finish();
+
+        let points_index = PointsOptions::new()
+            .metadata_path(&metadata)
+            .points_paths(&[sprint, fixes])
+            .add_points(&mut diff_total_collection)
+            .unwrap();
+
+        assert_eq!(points_index.get("S-10001"), Some("5"));
+        assert_eq!(diff_total_collection.totals["S-10001"].points, "5");
+        assert_eq!(diff_total_collection.totals["S-10002"].points, "2");
+    }
+
+    #[test]
+    fn strict_points_fails_on_conflicts_in_a_file() {
+        let dir = TempDir::new().unwrap();
+        let aliases = write_file(
+            &dir,
+            "aliases.json",
+            r#"{"aliases": [{"alias": "OLD-1", "story_number": "NEW-1"},
+                            {"alias": "OLD-1", "story_number": "NEW-2"}]}"#,
+        );
+        let options = PointsOptions::new().alias_path(&aliases);
+
+        assert_eq!(options.load_aliases().unwrap().resolve("OLD-1"), "NEW-1");
+        match options.strict_points(true).load_aliases() {
+            Err(CliError::BadPointsFile(error)) => {
+                assert!(
+                    error.to_string().contains("OLD-1 => NEW-1 / NEW-2"),
+                    "{}",
+                    error
+                )
+            }
+            other => panic!("expected a bad points file, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn reads_collections_and_lines_of_diffs() {
+        let dir = TempDir::new().unwrap();
+        let diff = |insertions: usize| DiffResult {
+            insertions,
+            ..DiffResult::default()
+        };
+        let collection = DiffCollection {
+            diffs: vec![diff(2)],
+            ..DiffCollection::default()
+        };
+        let lines = [diff(3), diff(4)]
+            .iter()
+            .map(|diff_result| serde_json::to_string(diff_result).unwrap() + "\n")
+            .collect::<String>();
+        let collection = write_file(
+            &dir,
+            "collection.json",
+            &serde_json::to_string(&collection).unwrap(),
+        );
+        let lines = write_file(&dir, "lines.jsonl", &lines);
+
+        let mut diffs: Vec<DiffResult> = Vec::new();
+        read_inputs(
+            &[collection, lines],
+            Reverts::Keep,
+            CherryPicks::Keep,
+            |diff_collection| diffs.extend(diff_collection.diffs),
+        )
+        .unwrap();
+
+        let insertions: Vec<usize> = diffs.iter().map(|diff| diff.insertions).collect();
+        assert_eq!(insertions, vec![2, 3, 4]);
+    }
+}
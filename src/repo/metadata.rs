@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io::Read;
+
+use crate::repo::diff::{
+    merge_entries, normalize_story_number, DiffTotal, DiffTotalCollection, StoryPoint,
+    StoryPointCollection,
+};
+
+/// Issue tracker details for a single story, as exported from the tracker.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoryMetadata {
+    pub story_number: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub issue_type: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub points: Option<String>,
+}
+
+impl fmt::Display for StoryMetadata {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.story_number)?;
+        if let Some(title) = &self.title {
+            write!(f, " {}", title)?;
+        }
+
+        let details: Vec<String> = vec![
+            self.issue_type.clone(),
+            self.status.clone(),
+            self.points.as_ref().map(|points| format!("{}sp", points)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoryMetadataCollection {
+    pub stories: Vec<StoryMetadata>,
+}
+
+impl StoryMetadataCollection {
+    /// Reads a CSV export with a header row naming the `StoryMetadata` fields.
+    /// Only `story_number` is required; empty cells are treated as missing.
+    pub fn from_csv<R: Read>(reader: R) -> Result<StoryMetadataCollection, csv::Error> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let stories = csv_reader
+            .deserialize()
+            .collect::<Result<Vec<StoryMetadata>, csv::Error>>()?;
+
+        Ok(StoryMetadataCollection { stories })
+    }
+
+    /// Merges another collection into this one. Entries from `other` replace existing entries
+    /// for the same story; within `other` the first entry for a story wins.
+    pub fn merge(&mut self, other: StoryMetadataCollection) -> &mut Self {
+        merge_entries(&mut self.stories, other.stories, |story| {
+            &story.story_number
+        });
+        self
+    }
+
+    /// The points recorded in the export, for merging with points files.
+    pub fn story_points(&self) -> StoryPointCollection {
+        let story_points = self
+            .stories
+            .iter()
+            .filter_map(|story| {
+                story.points.as_ref().map(|points| StoryPoint {
+                    story_number: story.story_number.clone(),
+                    points: points.clone(),
+                })
+            })
+            .collect();
+
+        StoryPointCollection { story_points }
+    }
+
+    /// Builds a lookup keyed by normalized story number. The first entry for a story wins.
+    pub fn index(&self) -> StoryMetadataIndex {
+        let mut stories: HashMap<String, StoryMetadata> =
+            HashMap::with_capacity(self.stories.len());
+
+        for story in self.stories.iter() {
+            stories
+                .entry(normalize_story_number(&story.story_number))
+                .or_insert_with(|| story.clone());
+        }
+
+        StoryMetadataIndex { stories }
+    }
+}
+
+impl fmt::Display for StoryMetadataCollection {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for story in self.stories.iter() {
+            writeln!(f, "{}", story)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct StoryMetadataIndex {
+    stories: HashMap<String, StoryMetadata>,
+}
+
+impl StoryMetadataIndex {
+    pub fn get(&self, story_number: &str) -> Option<&StoryMetadata> {
+        self.stories.get(&normalize_story_number(story_number))
+    }
+}
+
+pub trait Describable {
+    fn add_metadata(&mut self, metadata_index: &StoryMetadataIndex) -> &mut Self;
+}
+
+impl Describable for DiffTotal {
+    fn add_metadata(&mut self, metadata_index: &StoryMetadataIndex) -> &mut Self {
+        if let Some(story) = metadata_index.get(&self.story_number) {
+            self.title = story.title.clone();
+            self.issue_type = story.issue_type.clone();
+            self.status = story.status.clone();
+        }
+        for child in self.children.values_mut() {
+            child.add_metadata(metadata_index);
+        }
+
+        self
+    }
+}
+
+impl Describable for DiffTotalCollection {
+    fn add_metadata(&mut self, metadata_index: &StoryMetadataIndex) -> &mut Self {
+        for total in self.totals.values_mut() {
+            total.add_metadata(metadata_index);
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_csv_exports() {
+        let csv = "story_number,title,issue_type,status,points\n\
+                   SO-123, Login page ,Story,Done,5\n\
+                   so 124,,Bug,,\n";
+
+        let collection = StoryMetadataCollection::from_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(collection.stories.len(), 2);
+        let login = &collection.stories[0];
+        assert_eq!(login.title.as_deref(), Some("Login page"));
+        assert_eq!(login.points.as_deref(), Some("5"));
+        // empty cells are missing values
+        let bug = &collection.stories[1];
+        assert_eq!(bug.title, None);
+        assert_eq!(bug.issue_type.as_deref(), Some("Bug"));
+        assert_eq!(collection.story_points().index().get("SO-124"), None);
+    }
+
+    #[test]
+    fn reads_json_exports() {
+        let json = r#"{"stories": [
+            {"story_number": "SO-123", "title": "Login page", "points": "5"},
+            {"story_number": "SO-124", "status": "In Progress"}
+        ]}"#;
+
+        let collection: StoryMetadataCollection = serde_json::from_str(json).unwrap();
+        let index = collection.index();
+
+        assert_eq!(
+            index.get("so-123").unwrap().title.as_deref(),
+            Some("Login page")
+        );
+        assert_eq!(
+            index.get("SO 124").unwrap().status.as_deref(),
+            Some("In Progress")
+        );
+        assert_eq!(collection.story_points().story_points.len(), 1);
+    }
+
+    #[test]
+    fn later_exports_override_earlier_ones() {
+        let story = |story_number: &str, title: &str| StoryMetadata {
+            story_number: story_number.to_string(),
+            title: Some(title.to_string()),
+            issue_type: None,
+            status: None,
+            points: None,
+        };
+        let mut merged = StoryMetadataCollection {
+            stories: vec![story("SO-123", "Old title"), story("SO-124", "Kept")],
+        };
+        merged.merge(StoryMetadataCollection {
+            stories: vec![
+                story("so 123", "New title"),
+                story("SO-123", "Ignored"),
+                story("SO-125", "Added"),
+            ],
+        });
+
+        let index = merged.index();
+        assert_eq!(merged.stories.len(), 3);
+        assert_eq!(
+            index.get("SO-123").unwrap().title.as_deref(),
+            Some("New title")
+        );
+        assert_eq!(index.get("SO-124").unwrap().title.as_deref(), Some("Kept"));
+        assert_eq!(index.get("SO-125").unwrap().title.as_deref(), Some("Added"));
+    }
+}
@@ -1,153 +1,906 @@
-use git2::{Commit, Diff, DiffOptions, Error, Oid, Repository, Sort};
+use git2::{
+    Commit, Diff, DiffFindOptions, DiffOptions, Error, ErrorCode, Oid, Patch, Pathspec,
+    PathspecFlags, Repository, Revwalk, Sort,
+};
 use regex::Regex;
 use std::collections::HashMap;
 use std::string::ToString;
-
-use crate::errors::CliError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::errors::{CliError, CommitError, InputError};
+use crate::repo::aliases::StoryAliasIndex;
+use crate::repo::authors::AuthorFilter;
+use crate::repo::cache::DiffCache;
 use crate::repo::core::get_commit;
 use crate::repo::core::RepoPosition;
-use crate::repo::diff::{DiffCollection, DiffResult, DiffTotal, DiffTotalCollection};
-
+use crate::repo::diff::{
+    DiffCollection, DiffResult, DiffTotal, DiffTotalCollection, SkippedCommit,
+};
+use crate::repo::hierarchy::{Rollup, StoryParentIndex};
+pub use crate::repo::inputs::PointsOptions;
+pub use crate::repo::options::{CherryPicks, MergeMode, Period, Reverts, ShallowBoundary};
+
+pub mod aliases;
+mod authors;
+mod cache;
+pub mod comparison;
 mod core;
 pub mod diff;
+pub mod hierarchy;
+pub mod inputs;
+pub mod metadata;
+mod options;
+mod submodule;
+pub mod timeline;
+
+#[cfg(test)]
+mod tests;
+
+// matches 'SO-123', 'VEN-444'
+const JIRA_PATTERN: &str = r"([A-Za-z]+)[\s\-]*(\d+)";
+
+// matches 's-10345', 's 10345', 'd-10345', 'd 10345', 'S-10345', 'S 10345',
+// 'D-10345', 'D 10345', 's- 10345', 'S -10345', 'd  - 10345', 'D -  10345',
+// and backlog items and epics like 'B-10345' and 'E-10345', but not words ending in one of those
+// letters before a number, like 'release 20240'
+const V1_PATTERN: &str = r"\b([sdbeSDBE])[\s\-]*(\d{5})\b";
+
+/// Options for walking and diffing a repo. Start from `CollectOptions::new()`, which matches the
+/// command line defaults except for the diff cache, which is only used when asked for, and change
+/// what you need:
+///
+/// ```
+/// use story_line_counter::CollectOptions;
+///
+/// let options = CollectOptions::new().branch("main").skip_errors(true);
+/// assert_eq!(options.matcher, "v1");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CollectOptions {
+    /// The local branch to walk back from.
+    pub branch: String,
+    /// The story number schema to match in summaries, "v1" or "jira", or the name of
+    /// `matcher_pattern`.
+    pub matcher: String,
+    /// A custom story number pattern, used in place of the named schema. It must capture the
+    /// story's prefix and number, which are joined as 'PREFIX-number'.
+    pub matcher_pattern: Option<String>,
+    /// Changes to files matching these git pathspecs, e.g. 'vendor/' or '*.lock', aren't counted.
+    pub exclude_paths: Vec<String>,
+    /// The number of worker threads to diff with, 0 picks one per CPU.
+    pub jobs: usize,
+    /// Reuse and update the diff cache in the repo's git directory. Off by default, so nothing is
+    /// written to the repo unless asked for.
+    pub use_cache: bool,
+    /// Record commits that can't be read or diffed in `skipped_commits` instead of failing.
+    pub skip_errors: bool,
+    /// What to do with the boundary commits of a shallow clone.
+    pub shallow_boundary: ShallowBoundary,
+    /// How merged branches are walked.
+    pub merge_mode: MergeMode,
+    /// Whether reverted commits are counted along with their reverts.
+    pub reverts: Reverts,
+    /// Whether cherry-picked copies of changes already collected are counted.
+    pub cherry_picks: CherryPicks,
+    /// A name to tag each diff result with, so totals of several repos can be broken down by repo.
+    pub repo_name: Option<String>,
+    /// Count the commits a submodule moved through when a commit updates its pointer.
+    pub submodules: bool,
+    /// Only count commits whose author or committer matches one of these regexes, or email
+    /// domains like '@example.com'. Everyone is counted when empty.
+    pub authors: Vec<String>,
+    /// Leave out commits whose author or committer matches one of these regexes or domains.
+    pub exclude_authors: Vec<String>,
+    /// Leave out commits by well known bots, like dependabot and renovate.
+    pub exclude_bots: bool,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        CollectOptions {
+            branch: "master".to_string(),
+            matcher: "v1".to_string(),
+            matcher_pattern: None,
+            exclude_paths: Vec::new(),
+            jobs: 0,
+            use_cache: false,
+            skip_errors: false,
+            shallow_boundary: ShallowBoundary::default(),
+            merge_mode: MergeMode::default(),
+            reverts: Reverts::default(),
+            cherry_picks: CherryPicks::default(),
+            repo_name: None,
+            submodules: false,
+            authors: Vec::new(),
+            exclude_authors: Vec::new(),
+            exclude_bots: false,
+        }
+    }
+}
+
+impl CollectOptions {
+    pub fn new() -> CollectOptions {
+        CollectOptions::default()
+    }
+
+    pub fn branch(mut self, branch: &str) -> Self {
+        self.branch = branch.to_string();
+        self
+    }
+
+    pub fn matcher(mut self, matcher: &str) -> Self {
+        self.matcher = matcher.to_string();
+        self
+    }
+
+    /// Matches story numbers with a custom pattern, reported under the given matcher name.
+    pub fn matcher_pattern(mut self, matcher: &str, pattern: &str) -> Self {
+        self.matcher = matcher.to_string();
+        self.matcher_pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn exclude_path(mut self, pathspec: &str) -> Self {
+        self.exclude_paths.push(pathspec.to_string());
+        self
+    }
+
+    pub fn exclude_paths(mut self, pathspecs: &[String]) -> Self {
+        self.exclude_paths.extend_from_slice(pathspecs);
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn use_cache(mut self, use_cache: bool) -> Self {
+        self.use_cache = use_cache;
+        self
+    }
+
+    pub fn skip_errors(mut self, skip_errors: bool) -> Self {
+        self.skip_errors = skip_errors;
+        self
+    }
+
+    pub fn shallow_boundary(mut self, shallow_boundary: ShallowBoundary) -> Self {
+        self.shallow_boundary = shallow_boundary;
+        self
+    }
+
+    pub fn merge_mode(mut self, merge_mode: MergeMode) -> Self {
+        self.merge_mode = merge_mode;
+        self
+    }
+
+    pub fn reverts(mut self, reverts: Reverts) -> Self {
+        self.reverts = reverts;
+        self
+    }
+
+    pub fn cherry_picks(mut self, cherry_picks: CherryPicks) -> Self {
+        self.cherry_picks = cherry_picks;
+        self
+    }
+
+    pub fn repo_name(mut self, repo_name: &str) -> Self {
+        self.repo_name = Some(repo_name.to_string());
+        self
+    }
+
+    pub fn submodules(mut self, submodules: bool) -> Self {
+        self.submodules = submodules;
+        self
+    }
+
+    pub fn author(mut self, author: &str) -> Self {
+        self.authors.push(author.to_string());
+        self
+    }
+
+    pub fn authors(mut self, authors: &[String]) -> Self {
+        self.authors.extend_from_slice(authors);
+        self
+    }
+
+    pub fn exclude_author(mut self, author: &str) -> Self {
+        self.exclude_authors.push(author.to_string());
+        self
+    }
+
+    pub fn exclude_authors(mut self, authors: &[String]) -> Self {
+        self.exclude_authors.extend_from_slice(authors);
+        self
+    }
+
+    pub fn exclude_bots(mut self, exclude_bots: bool) -> Self {
+        self.exclude_bots = exclude_bots;
+        self
+    }
+
+    /// The regex story numbers are matched with.
+    pub fn story_pattern(&self) -> &str {
+        match (&self.matcher_pattern, self.matcher.as_str()) {
+            (Some(pattern), _) => pattern,
+            (None, "jira") => JIRA_PATTERN,
+            (None, _) => V1_PATTERN,
+        }
+    }
 
-struct OidPair(Oid, Oid);
+    // Everything that changes a DiffResult for the same commits
+    fn cache_key(&self) -> String {
+        format!(
+            "pattern={};merge-mode={};exclude={};submodules={}",
+            self.story_pattern(),
+            self.merge_mode,
+            self.exclude_paths.join(","),
+            self.submodules
+        )
+    }
+}
 
+// A commit and its diff against its first parent, or against the empty tree for a root commit
 struct CommitPair<'repo> {
-    first: Commit<'repo>,
-    second: Commit<'repo>,
+    parent: Option<Commit<'repo>>,
+    commit: Commit<'repo>,
     diff: Diff<'repo>,
 }
 
+/// Totals diff results one at a time, so a stream of them never has to be held in memory.
+#[derive(Debug, Default)]
+pub struct TotalAccumulator {
+    aliases: StoryAliasIndex,
+    rollup: Rollup,
+    totals: HashMap<String, DiffTotal>,
+    skipped_commits: usize,
+    history_truncated_at: Vec<String>,
+    excluded_by_author: usize,
+    skipped_submodules: usize,
+}
+
+impl TotalAccumulator {
+    pub fn new() -> TotalAccumulator {
+        TotalAccumulator::default()
+    }
+
+    /// Totals each story under its canonical story number, so a renamed story isn't split in two.
+    pub fn with_aliases(mut self, aliases: StoryAliasIndex) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Rolls each story up into the top of its hierarchy when the totals are finished.
+    pub fn with_hierarchy(mut self, parents: StoryParentIndex) -> Self {
+        self.rollup = Rollup::new(parents);
+        self
+    }
+
+    pub fn add(&mut self, diff_result: &DiffResult) {
+        self.skipped_submodules += diff_result.skipped_submodules.len();
+        if self.aliases.is_empty() {
+            add_diff_total(&mut self.totals, diff_result);
+            self.rollup.add(diff_result);
+        } else {
+            let mut diff_result = diff_result.clone();
+            self.aliases.apply(&mut diff_result);
+            add_diff_total(&mut self.totals, &diff_result);
+            self.rollup.add(&diff_result);
+        }
+    }
+
+    /// Adds a collection's diffs along with its skipped commits and truncated history.
+    pub fn add_collection(&mut self, diff_collection: DiffCollection) {
+        for diff_result in diff_collection.diffs.iter() {
+            self.add(diff_result);
+        }
+        self.skipped_commits += diff_collection.skipped_commits.len();
+        self.history_truncated_at
+            .extend(diff_collection.history_truncated_at);
+        self.excluded_by_author += diff_collection.excluded_by_author;
+    }
+
+    pub fn finish(self) -> DiffTotalCollection {
+        DiffTotalCollection {
+            totals: self.rollup.finish(self.totals),
+            skipped_commits: self.skipped_commits,
+            history_truncated_at: self.history_truncated_at,
+            excluded_by_author: self.excluded_by_author,
+            skipped_submodules: self.skipped_submodules,
+            reconciliation: None,
+        }
+    }
+}
+
 pub fn total(diff_collection: DiffCollection) -> Result<DiffTotalCollection, CliError> {
-    let totals = calculate_diff_totals(&diff_collection).map_err(CliError::Git)?;
-    Ok(DiffTotalCollection { totals })
+    let mut accumulator = TotalAccumulator::new();
+    accumulator.add_collection(diff_collection);
+    Ok(accumulator.finish())
+}
+
+pub fn collect(path: &str, options: &CollectOptions) -> Result<DiffCollection, CliError> {
+    let mut diffs: Vec<DiffResult> = Vec::new();
+    let mut diff_collection = collect_repo(path, options, &mut |diff_result| {
+        diffs.push(diff_result);
+        Ok(())
+    })?;
+    diff_collection.diffs = diffs;
+    filter_diffs(&mut diff_collection, options.reverts, options.cherry_picks);
+    Ok(diff_collection)
+}
+
+/// Collects a repo, handing each diff result to `on_diff` in walk order as soon as it's ready
+/// instead of holding them all. The returned collection has everything but the diffs.
+///
+/// Excluding reverts or deduping cherry-picks needs every diff at once, so with either enabled
+/// the results are only handed over once the whole repo has been collected.
+pub fn collect_each<F>(
+    path: &str,
+    options: &CollectOptions,
+    mut on_diff: F,
+) -> Result<DiffCollection, CliError>
+where
+    F: FnMut(DiffResult) -> Result<(), CliError>,
+{
+    if options.reverts == Reverts::Exclude || options.cherry_picks == CherryPicks::Dedupe {
+        let mut diff_collection = collect(path, options)?;
+        for diff_result in diff_collection.diffs.drain(..) {
+            on_diff(diff_result)?;
+        }
+        return Ok(diff_collection);
+    }
+
+    collect_repo(path, options, &mut on_diff)
 }
 
-pub fn collect(path: &str, branch: &str, matcher: &str) -> Result<DiffCollection, CliError> {
-    collect_repo(path, branch, matcher).map_err(CliError::Git)
+/// Applies the revert and cherry-pick handling to a collection, e.g. after combining
+/// collections from several branches.
+pub fn filter_diffs(
+    diff_collection: &mut DiffCollection,
+    reverts: Reverts,
+    cherry_picks: CherryPicks,
+) {
+    if reverts == Reverts::Exclude {
+        diff_collection.exclude_reverts();
+    }
+    if cherry_picks == CherryPicks::Dedupe {
+        diff_collection.dedupe_cherry_picks();
+    }
 }
 
-pub fn run(path: &str, branch: &str, matcher: &str) -> Result<DiffTotalCollection, CliError> {
-    let collection = collect_repo(path, branch, matcher).map_err(CliError::Git)?;
+pub fn run(path: &str, options: &CollectOptions) -> Result<DiffTotalCollection, CliError> {
+    let collection = collect(path, options)?;
     let diff_collection = total(collection)?;
     Ok(diff_collection)
 }
 
-fn get_story_numbers(summary: &str, matcher: &str) -> Result<Vec<String>, Error> {
-    let regex = match matcher {
-        // matches 'SO-123', 'VEN-444'
-        "jira" => Regex::new(r"([A-Za-z]+)[\s\-]*(\d+)").unwrap(),
+/// Removes the diff cache for the repo at `path`. Returns false if there was no cache.
+pub fn clear_cache(path: &str) -> Result<bool, CliError> {
+    let repo = open_repository(path)?;
+    let cleared = cache::clear(repo.path())?;
+    Ok(cleared)
+}
 
-        // matches 's-10345', 's 10345', 'd-10345', 'd 10345', 'S-10345', 'S 10345',
-        // 'D-10345', 'D 10345', 's- 10345', 'S -10345', 'd  - 10345', 'D -  10345'
-        _ => Regex::new(r"([sdSD])[\s\-]*(\d{5})").unwrap(),
-    };
+/// Whether the repo at `path` is a partial clone, whose missing objects can't be read.
+pub fn is_partial_clone(path: &str) -> Result<bool, CliError> {
+    let repo = open_repository(path)?;
+    let partial_clone = core::is_partial_clone(&repo)?;
+    Ok(partial_clone)
+}
+
+// The patterns read from each commit message, compiled once for a whole collection
+struct MessagePatterns {
+    story: Regex,
+    reverts_commit: Regex,
+    revert_subject: Regex,
+    cherry_picked: Regex,
+}
 
-    let mut story_numbers = Vec::new();
+impl MessagePatterns {
+    // Fails on an unknown matcher, or a pattern that doesn't compile or capture a prefix and number
+    fn new(options: &CollectOptions) -> Result<MessagePatterns, CliError> {
+        if options.matcher_pattern.is_none() && !["v1", "jira"].contains(&options.matcher.as_str())
+        {
+            return Err(CliError::from(InputError::from(format!(
+                "Unknown matcher '{}'. Use v1, jira, or a matcher defined in a config file.",
+                options.matcher
+            ))));
+        }
 
-    for cap in regex.captures_iter(summary) {
-        // 0 refers to the entire match
-        let story_type = &cap[1];
-        let story_number = &cap[2];
-        story_numbers.push(format!("{}-{}", story_type.to_uppercase(), story_number));
+        let pattern = options.story_pattern();
+        let story = Regex::new(pattern)?;
+        if story.captures_len() < 3 {
+            return Err(CliError::from(InputError::from(format!(
+                "The story number pattern '{}' must capture a prefix and a number.",
+                pattern
+            ))));
+        }
+
+        Ok(MessagePatterns {
+            story,
+            reverts_commit: Regex::new(r"This reverts commit ([0-9a-fA-F]{7,40})").unwrap(),
+            revert_subject: Regex::new(r#"^Revert "(.+)""#).unwrap(),
+            cherry_picked: Regex::new(r"\(cherry picked from commit ([0-9a-fA-F]{7,40})\)")
+                .unwrap(),
+        })
+    }
+
+    fn story_numbers(&self, summary: &str) -> Vec<String> {
+        let mut story_numbers = Vec::new();
+
+        for cap in self.story.captures_iter(summary) {
+            // 0 refers to the entire match
+            let story_type = &cap[1];
+            let story_number = &cap[2];
+            let story_number = format!("{}-{}", story_type.to_uppercase(), story_number);
+
+            // a summary naming a story twice, e.g. 'so-12: finish SO 12', counts it once
+            if !story_numbers.contains(&story_number) {
+                story_numbers.push(story_number);
+            }
+        }
+
+        story_numbers
     }
 
-    Ok(story_numbers)
+    // Reads the id from git's `This reverts commit <id>` line, falling back to the summary quoted
+    // in a `Revert "<summary>"` subject, which is all a squash-merged revert may keep
+    fn reverted_commit(&self, message: &str) -> Option<String> {
+        if let Some(cap) = self.reverts_commit.captures(message) {
+            return Some(cap[1].to_lowercase());
+        }
+
+        let summary = message.lines().next().unwrap_or("");
+        self.revert_subject
+            .captures(summary)
+            .map(|cap| cap[1].to_string())
+    }
+
+    // `git cherry-pick -x` appends `(cherry picked from commit <id>)` to the message
+    fn cherry_pick_source(&self, message: &str) -> Option<String> {
+        self.cherry_picked
+            .captures(message)
+            .map(|cap| cap[1].to_lowercase())
+    }
+}
+
+fn open_repository(path: &str) -> Result<Repository, CliError> {
+    core::get_repository(path).map_err(|error| match error.code() {
+        ErrorCode::NotFound => CliError::RepoNotFound(path.to_string(), error),
+        _ => CliError::Git(error),
+    })
 }
 
 // Loads a repo, parses the tree, and builds a map of story numbers -> diff
-fn collect_repo(repo_path: &str, branch: &str, matcher: &str) -> Result<DiffCollection, Error> {
-    let repo = core::get_repository(repo_path)?;
-    let repo_start = core::get_repo_head(&repo, branch)?;
-    let diff_collection = collect_diffs(&repo_start, matcher)?;
+fn collect_repo(
+    repo_path: &str,
+    options: &CollectOptions,
+    emit: &mut dyn FnMut(DiffResult) -> Result<(), CliError>,
+) -> Result<DiffCollection, CliError> {
+    let repo = open_repository(repo_path)?;
+    let repo_start =
+        core::get_repo_head(&repo, &options.branch).map_err(|error| match error.code() {
+            ErrorCode::NotFound => CliError::BranchNotFound(options.branch.to_string(), error),
+            _ => CliError::Git(error),
+        })?;
+    // tagged after the cache, so the same cached diffs serve under any name
+    let diff_collection = collect_diffs(&repo_start, options, &mut |mut diff_result| {
+        diff_result.repo = options.repo_name.clone();
+        emit(diff_result)
+    })?;
     Ok(diff_collection)
 }
 
-fn collect_diffs(start: &RepoPosition, matcher: &str) -> Result<DiffCollection, Error> {
-    let RepoPosition {
-        repository,
-        branch: _,
-        commit,
-    } = start;
-
-    let mut first_rev_collection = repository.revwalk()?;
-    first_rev_collection.set_sorting(Sort::TOPOLOGICAL);
-    first_rev_collection.simplify_first_parent();
-    first_rev_collection.push(commit.id())?;
-
-    let mut second_rev_collection = repository.revwalk()?;
-    second_rev_collection.set_sorting(Sort::TOPOLOGICAL);
-    second_rev_collection.simplify_first_parent();
-    second_rev_collection.push(commit.id())?;
-
-    let mut first_commit_iterator = first_rev_collection.into_iter();
-    let second_commit_iterator = second_rev_collection.into_iter();
-    first_commit_iterator.next();
-
-    let result: Result<Vec<DiffResult>, Error> = first_commit_iterator
-        .zip(second_commit_iterator)
-        .filter(|oids| {
-            let (first, second) = oids;
-            first.is_ok() && second.is_ok()
-        })
-        .map(|oids| {
-            let (first, second) = oids;
-            OidPair(first.unwrap(), second.unwrap())
+// Emits each diff result in walk order and returns the rest of the collection, with no diffs
+fn collect_diffs(
+    start: &RepoPosition,
+    options: &CollectOptions,
+    emit: &mut dyn FnMut(DiffResult) -> Result<(), CliError>,
+) -> Result<DiffCollection, CliError> {
+    // compiled once up front, so a bad pattern fails the run instead of every commit
+    let patterns = MessagePatterns::new(options)?;
+    let author_filter = AuthorFilter::new(options)?;
+    let mut excluded_by_author = 0;
+
+    let git_dir = start.repository.path();
+    let options_key = options.cache_key();
+    let mut cache = if options.use_cache {
+        DiffCache::load(git_dir)
+    } else {
+        DiffCache::default()
+    };
+    let mut skipped_commits: Vec<SkippedCommit> = Vec::new();
+
+    let shallow_boundaries = core::get_shallow_boundaries(start.repository)?;
+    let mut history_truncated_at: Vec<String> = Vec::new();
+
+    let mut oids: Vec<Oid> = Vec::new();
+    for oid in get_rev_collection(start, options.merge_mode)? {
+        match oid {
+            Ok(oid) => {
+                // Every commit on a merged branch is counted on its own, so merges are left out;
+                // diffed against their first parent they would count the whole branch again
+                if options.merge_mode == MergeMode::AllCommits && is_merge(start.repository, oid) {
+                    continue;
+                }
+                if shallow_boundaries.contains(&oid) {
+                    history_truncated_at.push(oid.to_string());
+                    if options.shallow_boundary != ShallowBoundary::Flag {
+                        continue;
+                    }
+                }
+                if !author_filter.is_empty() {
+                    match get_commit(start.repository, &oid) {
+                        Ok(commit) if !author_filter.keeps(&commit) => {
+                            excluded_by_author += 1;
+                            continue;
+                        }
+                        Ok(_) => {}
+                        Err(error) => {
+                            let error = CommitError::commit(oid.to_string(), error);
+                            skip_or_fail(&mut skipped_commits, error, options)?;
+                            continue;
+                        }
+                    }
+                }
+                oids.push(oid);
+            }
+            Err(error) => skip_or_fail(&mut skipped_commits, CommitError::walk(error), options)?,
+        }
+    }
+
+    let jobs = match options.jobs {
+        0 => thread::available_parallelism().map_or(1, |jobs| jobs.get()),
+        jobs => jobs,
+    };
+
+    // Boundary commits are never cached, their diffs change once the clone is deepened
+    let uncached_oids: Vec<Oid> = oids
+        .iter()
+        .filter(|oid| {
+            shallow_boundaries.contains(oid) || cache.get(&options_key, &oid.to_string()).is_none()
         })
-        .filter_map(|oid_pair| get_commit_pair(&repository, oid_pair))
-        .map(|commit_pair| parse_commit_pair(&commit_pair, matcher))
+        .copied()
         .collect();
 
-    match result {
-        Ok(diffs) => Ok(DiffCollection { diffs }),
-        Err(error) => Err(error),
+    // Diffs arrive in walk order, so each is emitted after the cached commits walked before it
+    let mut walk = oids.iter();
+    let mut cache_changed = false;
+    let mut receive = |oid: Oid, diff_result: CommitDiff| {
+        for cached_oid in walk.by_ref().take_while(|walked| **walked != oid) {
+            emit_cached(&cache, &options_key, cached_oid, emit)?;
+        }
+
+        match diff_result {
+            Ok(mut diff_result) => {
+                // a submodule left out may be readable next time, so that diff isn't cached
+                if history_truncated_at.contains(&diff_result.commit) {
+                    diff_result.shallow_boundary = true;
+                } else if options.use_cache && diff_result.skipped_submodules.is_empty() {
+                    cache.insert(
+                        &options_key,
+                        diff_result.commit.clone(),
+                        diff_result.clone(),
+                    );
+                    cache_changed = true;
+                }
+                emit(diff_result)
+            }
+            Err(error) => skip_or_fail(&mut skipped_commits, error, options),
+        }
+    };
+
+    if jobs > 1 && uncached_oids.len() > 1 {
+        diff_parallel(
+            start.repository,
+            &uncached_oids,
+            &patterns,
+            options,
+            jobs,
+            &mut receive,
+        )?;
+    } else {
+        diff_sequential(
+            start.repository,
+            &uncached_oids,
+            &patterns,
+            options,
+            &mut receive,
+        )?;
+    }
+    for cached_oid in walk {
+        emit_cached(&cache, &options_key, cached_oid, emit)?;
+    }
+
+    // the diffs are already collected, so a cache that can't be written only costs the next run
+    if cache_changed {
+        if let Err(error) = cache.save(git_dir) {
+            eprintln!("Warning: could not save the diff cache: {}", error);
+        }
     }
+
+    Ok(DiffCollection {
+        diffs: Vec::new(),
+        skipped_commits,
+        history_truncated_at,
+        excluded_by_author,
+    })
+}
+
+fn emit_cached(
+    cache: &DiffCache,
+    options_key: &str,
+    oid: &Oid,
+    emit: &mut dyn FnMut(DiffResult) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    match cache.get(options_key, &oid.to_string()) {
+        Some(diff_result) => emit(diff_result.clone()),
+        None => Ok(()),
+    }
+}
+
+fn skip_or_fail(
+    skipped_commits: &mut Vec<SkippedCommit>,
+    error: CommitError,
+    options: &CollectOptions,
+) -> Result<(), CliError> {
+    if !options.skip_errors {
+        return Err(CliError::Commit(error));
+    }
+
+    skipped_commits.push(SkippedCommit {
+        commit: error.commit.clone(),
+        reason: error.error.message().to_string(),
+    });
+    Ok(())
 }
 
-fn get_commit_pair(repository: &Repository, oid_pair: OidPair) -> Option<CommitPair> {
+fn get_rev_collection<'repo>(
+    start: &RepoPosition<'repo>,
+    merge_mode: MergeMode,
+) -> Result<Revwalk<'repo>, Error> {
+    let RepoPosition { repository, commit } = start;
+
+    let mut rev_collection = repository.revwalk()?;
+    rev_collection.set_sorting(Sort::TOPOLOGICAL)?;
+    if merge_mode != MergeMode::AllCommits {
+        rev_collection.simplify_first_parent()?;
+    }
+    rev_collection.push(commit.id())?;
+
+    Ok(rev_collection)
+}
+
+fn is_merge(repository: &Repository, oid: Oid) -> bool {
+    get_commit(repository, &oid).is_ok_and(|commit| commit.parent_count() > 1)
+}
+
+// The commits a merge brought in: those reachable from its other parents but not its first
+fn get_merged_commits<'repo>(
+    repository: &'repo Repository,
+    merge: &Commit<'repo>,
+) -> Result<Vec<Commit<'repo>>, Error> {
+    let mut rev_collection = repository.revwalk()?;
+    for parent_id in merge.parent_ids().skip(1) {
+        rev_collection.push(parent_id)?;
+    }
+    rev_collection.hide(merge.parent_id(0)?)?;
+
+    rev_collection
+        .map(|oid| oid.and_then(|oid| get_commit(repository, &oid)))
+        .collect()
+}
+
+// A commit's diff, or why it couldn't be diffed
+type CommitDiff = Result<DiffResult, CommitError>;
+
+// Both diff paths hand each commit's diff to `receive` in the order given, and stop at the
+// first error it returns
+fn diff_sequential(
+    repository: &Repository,
+    oids: &[Oid],
+    patterns: &MessagePatterns,
+    options: &CollectOptions,
+    receive: &mut dyn FnMut(Oid, CommitDiff) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    for oid in oids {
+        receive(*oid, diff_commit(repository, *oid, patterns, options))?;
+    }
+
+    Ok(())
+}
+
+// git2 handles aren't Sync, so each worker opens its own handle once and takes commits off a
+// shared queue for the whole walk. Diffs are handed on in walk order as they come in.
+fn diff_parallel(
+    repository: &Repository,
+    oids: &[Oid],
+    patterns: &MessagePatterns,
+    options: &CollectOptions,
+    jobs: usize,
+    receive: &mut dyn FnMut(Oid, CommitDiff) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    let repo_path = repository.path();
+    let (work_sender, work_receiver) = mpsc::channel();
+    for (index, oid) in oids.iter().enumerate() {
+        work_sender.send((index, *oid)).unwrap();
+    }
+    drop(work_sender);
+    let work = Mutex::new(work_receiver);
+    let stopped = AtomicBool::new(false);
+    let (diff_sender, diff_receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(oids.len()) {
+            let diff_sender = diff_sender.clone();
+            let (work, stopped) = (&work, &stopped);
+            scope.spawn(move || {
+                let repository = match Repository::open(repo_path) {
+                    Ok(repository) => repository,
+                    Err(error) => {
+                        diff_sender.send(Err(error)).ok();
+                        return;
+                    }
+                };
+
+                while !stopped.load(Ordering::Relaxed) {
+                    let (index, oid) = match work.lock().unwrap().recv() {
+                        Ok(next) => next,
+                        Err(_) => return,
+                    };
+                    let diff_result = diff_commit(&repository, oid, patterns, options);
+                    if diff_sender.send(Ok((index, diff_result))).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(diff_sender);
+
+        let received = receive_in_order(oids, diff_receiver, receive);
+        // workers finish the commit they're on and stop, if the run failed part way
+        stopped.store(true, Ordering::Relaxed);
+        received
+    })
+}
+
+// Holds on to diffs that finish early until every commit walked before them has been received
+fn receive_in_order(
+    oids: &[Oid],
+    diff_receiver: mpsc::Receiver<Result<(usize, CommitDiff), Error>>,
+    receive: &mut dyn FnMut(Oid, CommitDiff) -> Result<(), CliError>,
+) -> Result<(), CliError> {
+    let mut waiting: HashMap<usize, CommitDiff> = HashMap::new();
+    let mut next_index = 0;
+
+    for diff in diff_receiver {
+        let (index, diff_result) = diff?;
+        waiting.insert(index, diff_result);
+
+        while let Some(diff_result) = waiting.remove(&next_index) {
+            receive(oids[next_index], diff_result)?;
+            next_index += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn diff_commit(
+    repository: &Repository,
+    oid: Oid,
+    patterns: &MessagePatterns,
+    options: &CollectOptions,
+) -> Result<DiffResult, CommitError> {
+    get_commit_pair(repository, oid)
+        .and_then(|commit_pair| {
+            let mut diff_result = parse_commit_pair(repository, &commit_pair, patterns, options)?;
+            if options.merge_mode == MergeMode::MergeAsUnit && commit_pair.commit.parent_count() > 1
+            {
+                add_merged_story_numbers(
+                    repository,
+                    &commit_pair.commit,
+                    &mut diff_result,
+                    patterns,
+                )?;
+            }
+            Ok(diff_result)
+        })
+        .map_err(|error| CommitError::commit(oid.to_string(), error))
+}
+
+// Attributes a merge to the stories named by any commit on the branch it merged
+fn add_merged_story_numbers(
+    repository: &Repository,
+    merge: &Commit,
+    diff_result: &mut DiffResult,
+    patterns: &MessagePatterns,
+) -> Result<(), Error> {
+    for commit in get_merged_commits(repository, merge)? {
+        let summary = commit.summary().unwrap_or("");
+        for story_number in patterns.story_numbers(summary) {
+            if !diff_result.story_number.contains(&story_number) {
+                diff_result.story_number.push(story_number);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_commit_pair(repository: &Repository, oid: Oid) -> Result<CommitPair<'_>, Error> {
     let mut diff_options = DiffOptions::new();
 
-    let OidPair(first_oid, second_oid) = oid_pair;
-    let first_commit = get_commit(&repository, &first_oid).unwrap();
-    let second_commit = get_commit(&repository, &second_oid).unwrap();
+    let commit = get_commit(repository, &oid)?;
+    let parent = match commit.parent_count() {
+        0 => None,
+        _ => Some(commit.parent(0)?),
+    };
+    let parent_tree = match &parent {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
 
-    let diff = repository
-        .diff_tree_to_tree(
-            Some(&first_commit.tree().unwrap()),
-            Some(&second_commit.tree().unwrap()),
-            Some(&mut diff_options),
-        )
-        .unwrap();
+    let mut diff = repository.diff_tree_to_tree(
+        parent_tree.as_ref(),
+        Some(&commit.tree()?),
+        Some(&mut diff_options),
+    )?;
+    find_renames(&mut diff)?;
 
-    Some(CommitPair {
-        first: first_commit,
-        second: second_commit,
+    Ok(CommitPair {
+        parent,
+        commit,
         diff,
     })
 }
 
-fn parse_commit_pair(diff: &CommitPair, matcher: &str) -> Result<DiffResult, Error> {
+// Pairs up deleted and added files that are renames, as `git diff` does, so a moved file counts
+// as one file changed instead of all of its lines removed and added again
+fn find_renames(diff: &mut Diff) -> Result<(), Error> {
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+}
+
+fn parse_commit_pair(
+    repository: &Repository,
+    diff: &CommitPair,
+    patterns: &MessagePatterns,
+    options: &CollectOptions,
+) -> Result<DiffResult, Error> {
     let CommitPair {
-        first,
-        second,
+        parent,
+        commit,
         diff,
     } = diff;
 
-    let first_summary = first.summary().unwrap_or("").to_string();
-    let second_summary = second.summary().unwrap_or("").to_string();
+    let first_summary = parent
+        .as_ref()
+        .and_then(|parent| parent.summary())
+        .unwrap_or("")
+        .to_string();
+    let second_summary = commit.summary().unwrap_or("").to_string();
+    let message = commit.message().unwrap_or("");
 
-    let story_number = match get_story_numbers(&second_summary, matcher) {
-        Ok(story_number) => story_number,
-        Err(_) => vec!["orphan".to_string()],
-    };
+    let story_number = patterns.story_numbers(&second_summary);
 
-    let diff_stats = diff.stats()?;
-    let files_changed = diff_stats.files_changed();
-    let insertions = diff_stats.insertions();
-    let deletions = diff_stats.deletions();
+    let (mut files_changed, mut insertions, mut deletions) =
+        get_diff_stats(diff, &options.exclude_paths)?;
+
+    let (submodules, skipped_submodules) = match options.submodules {
+        true => submodule::get_submodule_changes(repository, diff, &options.exclude_paths),
+        false => (Vec::new(), Vec::new()),
+    };
+    for change in submodules.iter() {
+        files_changed += change.files_changed;
+        insertions += change.insertions;
+        deletions += change.deletions;
+    }
 
     Ok(DiffResult {
+        commit: commit.id().to_string(),
         story_number,
         points: "0".to_string(),
         first_summary,
@@ -155,47 +908,52 @@ fn parse_commit_pair(diff: &CommitPair, matcher: &str) -> Result<DiffResult, Err
         files_changed,
         insertions,
         deletions,
+        timestamp: Some(commit.time().seconds()),
+        shallow_boundary: false,
+        reverts: patterns.reverted_commit(message),
+        cherry_picked_from: patterns.cherry_pick_source(message),
+        repo: None,
+        submodules,
+        skipped_submodules,
     })
 }
 
-fn calculate_diff_totals(
-    diff_collection: &DiffCollection,
-) -> Result<HashMap<String, DiffTotal>, Error> {
-    let mut diff_totals_sum: HashMap<String, DiffTotal> = HashMap::new();
-
-    diff_collection.diffs.iter().for_each(|diff_result| {
-        //        println!("{}", diff_result.to_string());
-        for story_number in diff_result.story_number.iter() {
-            match diff_totals_sum.get(story_number) {
-                Some(diff_total) => {
-                    let new_total = DiffTotal {
-                        story_number: (*(diff_total).story_number).to_string(),
-                        points: "0".to_string(),
-                        files_changed: diff_total.files_changed + diff_result.insertions,
-                        insertions: diff_total.insertions + diff_result.deletions,
-                        deletions: diff_total.deletions + diff_result.files_changed,
-                        total_diff_results: diff_total.total_diff_results + 1,
-                    };
+// Returns (files changed, insertions, deletions), leaving out files matching the excluded pathspecs
+fn get_diff_stats(diff: &Diff, exclude_paths: &[String]) -> Result<(usize, usize, usize), Error> {
+    if exclude_paths.is_empty() {
+        let diff_stats = diff.stats()?;
+        return Ok((
+            diff_stats.files_changed(),
+            diff_stats.insertions(),
+            diff_stats.deletions(),
+        ));
+    }
 
-                    //println!("{}", new_total.to_string());
-                    diff_totals_sum.insert(story_number.to_string(), new_total);
-                }
-                None => {
-                    let new_total = DiffTotal {
-                        story_number: story_number.to_string(),
-                        points: "0".to_string(),
-                        files_changed: diff_result.files_changed,
-                        insertions: diff_result.insertions,
-                        deletions: diff_result.deletions,
-                        total_diff_results: 1,
-                    };
+    // libgit2 ignores '!' exclusions in a diff's own pathspec, so files are matched one by one
+    let excluded = Pathspec::new(exclude_paths.iter())?;
+    let mut stats = (0, 0, 0);
+    for (index, delta) in diff.deltas().enumerate() {
+        let path = delta.new_file().path().or_else(|| delta.old_file().path());
+        if path.is_some_and(|path| excluded.matches_path(path, PathspecFlags::DEFAULT)) {
+            continue;
+        }
 
-                    //println!("{}", new_total.to_string());
-                    diff_totals_sum.insert(story_number.to_string(), new_total);
-                }
-            }
+        stats.0 += 1;
+        if let Some(patch) = Patch::from_diff(diff, index)? {
+            let (_, insertions, deletions) = patch.line_stats()?;
+            stats.1 += insertions;
+            stats.2 += deletions;
         }
-    });
+    }
 
-    Ok(diff_totals_sum)
+    Ok(stats)
+}
+
+fn add_diff_total(diff_totals_sum: &mut HashMap<String, DiffTotal>, diff_result: &DiffResult) {
+    for story_number in diff_result.story_number.iter() {
+        diff_totals_sum
+            .entry(story_number.to_string())
+            .or_insert_with(|| DiffTotal::new(story_number))
+            .add(diff_result);
+    }
 }
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::errors::InputError;
+
+/// What to do with the boundary commits of a shallow clone, whose parents are missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShallowBoundary {
+    /// Leave them out.
+    #[default]
+    Exclude,
+    /// Keep them, with `shallow_boundary` set on their diff results.
+    Flag,
+}
+
+impl ShallowBoundary {
+    pub const VALUES: &'static [&'static str] = &["exclude", "flag"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShallowBoundary::Exclude => "exclude",
+            ShallowBoundary::Flag => "flag",
+        }
+    }
+}
+
+impl FromStr for ShallowBoundary {
+    type Err = InputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "exclude" => Ok(ShallowBoundary::Exclude),
+            "flag" => Ok(ShallowBoundary::Flag),
+            _ => Err(unknown_value("shallow boundary", value, Self::VALUES)),
+        }
+    }
+}
+
+/// How merged branches are walked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeMode {
+    /// Follow only the first parent of each merge.
+    #[default]
+    FirstParent,
+    /// Count each commit on merged branches, instead of the merges. Changes made in a merge
+    /// itself, like conflict resolutions, aren't counted.
+    AllCommits,
+    /// Count each merge as one diff, attributed to the stories of every commit it brought in.
+    MergeAsUnit,
+}
+
+impl MergeMode {
+    pub const VALUES: &'static [&'static str] = &["first-parent", "all-commits", "merge-as-unit"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MergeMode::FirstParent => "first-parent",
+            MergeMode::AllCommits => "all-commits",
+            MergeMode::MergeAsUnit => "merge-as-unit",
+        }
+    }
+}
+
+impl FromStr for MergeMode {
+    type Err = InputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "first-parent" => Ok(MergeMode::FirstParent),
+            "all-commits" => Ok(MergeMode::AllCommits),
+            "merge-as-unit" => Ok(MergeMode::MergeAsUnit),
+            _ => Err(unknown_value("merge mode", value, Self::VALUES)),
+        }
+    }
+}
+
+/// What to do with reverted commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reverts {
+    /// Count reverted commits and their reverts.
+    #[default]
+    Keep,
+    /// Drop reverted commits along with their reverts.
+    Exclude,
+}
+
+impl Reverts {
+    pub const VALUES: &'static [&'static str] = &["keep", "exclude"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reverts::Keep => "keep",
+            Reverts::Exclude => "exclude",
+        }
+    }
+}
+
+impl FromStr for Reverts {
+    type Err = InputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keep" => Ok(Reverts::Keep),
+            "exclude" => Ok(Reverts::Exclude),
+            _ => Err(unknown_value("reverts option", value, Self::VALUES)),
+        }
+    }
+}
+
+/// What to do with cherry-picked copies of a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CherryPicks {
+    /// Count every copy.
+    #[default]
+    Keep,
+    /// Drop copies of changes already collected.
+    Dedupe,
+}
+
+impl CherryPicks {
+    pub const VALUES: &'static [&'static str] = &["keep", "dedupe"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CherryPicks::Keep => "keep",
+            CherryPicks::Dedupe => "dedupe",
+        }
+    }
+}
+
+impl FromStr for CherryPicks {
+    type Err = InputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keep" => Ok(CherryPicks::Keep),
+            "dedupe" => Ok(CherryPicks::Dedupe),
+            _ => Err(unknown_value("cherry-picks option", value, Self::VALUES)),
+        }
+    }
+}
+
+/// The calendar period a timeline buckets commits by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Period {
+    Day,
+    /// Weeks starting on Monday.
+    #[default]
+    Week,
+    Month,
+}
+
+impl Period {
+    pub const VALUES: &'static [&'static str] = &["day", "week", "month"];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = InputError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "day" => Ok(Period::Day),
+            "week" => Ok(Period::Week),
+            "month" => Ok(Period::Month),
+            _ => Err(unknown_value("period", value, Self::VALUES)),
+        }
+    }
+}
+
+impl fmt::Display for ShallowBoundary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for MergeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Reverts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for CherryPicks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// e.g. "Unknown merge mode 'first_parent'. Use first-parent, all-commits or merge-as-unit."
+fn unknown_value(option: &str, value: &str, values: &[&str]) -> InputError {
+    let (last, rest) = values.split_last().unwrap_or((&"", &[]));
+    InputError::from(format!(
+        "Unknown {} '{}'. Use {} or {}.",
+        option,
+        value,
+        rest.join(", "),
+        last
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_value_it_lists() {
+        for value in MergeMode::VALUES {
+            assert_eq!(value.parse::<MergeMode>().unwrap().as_str(), *value);
+        }
+        for value in ShallowBoundary::VALUES {
+            assert_eq!(value.parse::<ShallowBoundary>().unwrap().as_str(), *value);
+        }
+        for value in Period::VALUES {
+            assert_eq!(value.parse::<Period>().unwrap().as_str(), *value);
+        }
+        assert_eq!("exclude".parse::<Reverts>().unwrap(), Reverts::Exclude);
+        assert_eq!(
+            "dedupe".parse::<CherryPicks>().unwrap(),
+            CherryPicks::Dedupe
+        );
+    }
+
+    #[test]
+    fn rejects_typos() {
+        let error = "first_parent".parse::<MergeMode>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown merge mode 'first_parent'. Use first-parent, all-commits or merge-as-unit."
+        );
+        assert!("drop ".parse::<Reverts>().is_err());
+        assert!("Dedupe".parse::<CherryPicks>().is_err());
+    }
+}
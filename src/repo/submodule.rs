@@ -0,0 +1,93 @@
+use git2::{Delta, Diff, Error, ErrorCode, FileMode, Oid, Repository};
+use std::path::Path;
+
+use crate::repo::diff::{SkippedSubmodule, SubmoduleChange};
+use crate::repo::{find_renames, get_diff_stats};
+
+/// Lists the submodules a diff moves from one commit to another, each with the counts of the
+/// diff between those commits. Added and removed submodules are left out, since their whole
+/// tree would be counted. Submodules that can't be read are listed separately, so the rest of
+/// the commit is still counted.
+pub fn get_submodule_changes(
+    repository: &Repository,
+    diff: &Diff,
+    exclude_paths: &[String],
+) -> (Vec<SubmoduleChange>, Vec<SkippedSubmodule>) {
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+
+    for delta in diff.deltas() {
+        let (old_file, new_file) = (delta.old_file(), delta.new_file());
+        if delta.status() != Delta::Modified
+            || old_file.mode() != FileMode::Commit
+            || new_file.mode() != FileMode::Commit
+        {
+            continue;
+        }
+
+        let path = match new_file.path() {
+            Some(path) => path,
+            None => continue,
+        };
+        match get_submodule_change(
+            repository,
+            path,
+            old_file.id(),
+            new_file.id(),
+            exclude_paths,
+        ) {
+            Ok(change) => changes.push(change),
+            Err(error) => skipped.push(SkippedSubmodule {
+                path: path.to_string_lossy().to_string(),
+                reason: error.message().to_string(),
+            }),
+        }
+    }
+
+    (changes, skipped)
+}
+
+fn get_submodule_change(
+    repository: &Repository,
+    path: &Path,
+    from: Oid,
+    to: Oid,
+    exclude_paths: &[String],
+) -> Result<SubmoduleChange, Error> {
+    let submodule_repository = open_submodule(repository, path)?;
+    let from_tree = submodule_repository.find_commit(from)?.tree()?;
+    let to_tree = submodule_repository.find_commit(to)?.tree()?;
+
+    let mut diff =
+        submodule_repository.diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)?;
+    find_renames(&mut diff)?;
+    let (files_changed, insertions, deletions) = get_diff_stats(&diff, exclude_paths)?;
+
+    Ok(SubmoduleChange {
+        path: path.to_string_lossy().to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        files_changed,
+        insertions,
+        deletions,
+    })
+}
+
+// A checked out submodule is opened from its working directory; otherwise from the copy git
+// keeps in the superproject's modules directory
+fn open_submodule(repository: &Repository, path: &Path) -> Result<Repository, Error> {
+    let submodule_path = path.to_string_lossy();
+    // a path only found in history, e.g. after the submodule moved, has no entry to open it by
+    let submodule = repository
+        .find_submodule(&submodule_path)
+        .map_err(|error| match error.code() {
+            ErrorCode::NotFound => Error::from_str("not listed in .gitmodules at this path"),
+            _ => error,
+        })?;
+
+    submodule.open().or_else(|_| {
+        let name = submodule.name().unwrap_or(&submodule_path);
+        Repository::open(repository.path().join("modules").join(name))
+            .map_err(|_| Error::from_str("not initialized; run git submodule update --init"))
+    })
+}
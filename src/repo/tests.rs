@@ -0,0 +1,905 @@
+use git2::{Oid, Repository, Signature};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+use crate::errors::CliError;
+use crate::repo::aliases::{StoryAlias, StoryAliasCollection};
+use crate::repo::cache::{get_cache_path, DiffCache};
+use crate::repo::diff::{DiffCollection, DiffResult};
+use crate::repo::{
+    clear_cache, collect, collect_each, total, CherryPicks, CollectOptions, MergeMode,
+    MessagePatterns, Reverts, ShallowBoundary, TotalAccumulator,
+};
+
+// The id git gives an empty tree, used to diff a root commit
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// A throwaway repository on `master` built one commit at a time.
+pub struct TestRepo {
+    pub dir: TempDir,
+    pub repository: Repository,
+}
+
+impl TestRepo {
+    pub fn new() -> TestRepo {
+        let dir = TempDir::new().unwrap();
+        let repository = Repository::init(dir.path()).unwrap();
+        repository.set_head("refs/heads/master").unwrap();
+
+        TestRepo { dir, repository }
+    }
+
+    pub fn path(&self) -> &str {
+        self.dir.path().to_str().unwrap()
+    }
+
+    /// Writes the given files, deleting those with no content, and commits them on HEAD.
+    pub fn commit(&self, message: &str, files: &[(&str, Option<&str>)]) -> Oid {
+        for (name, content) in files {
+            let path = self.dir.path().join(name);
+            match content {
+                Some(content) => fs::write(&path, content).unwrap(),
+                None => fs::remove_file(&path).unwrap(),
+            }
+        }
+
+        let mut index = self.repository.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"].iter(), None).unwrap();
+        index.write().unwrap();
+        let tree = self
+            .repository
+            .find_tree(index.write_tree().unwrap())
+            .unwrap();
+
+        let signature = Signature::now("Tester", "tester@example.com").unwrap();
+        let parent = self
+            .repository
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+        self.repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+    }
+}
+
+pub fn options() -> CollectOptions {
+    CollectOptions::new().jobs(1).use_cache(false)
+}
+
+// Returns (files changed, insertions, deletions) as reported by `git diff --shortstat`
+fn git_shortstat(repo_path: &Path, from: &str, to: &str) -> (usize, usize, usize) {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_path)
+        .args(["diff", "--shortstat", from, to])
+        .output()
+        .expect("git must be installed to run these tests");
+    let shortstat = String::from_utf8(output.stdout).unwrap();
+
+    let count = |pattern: &str| {
+        Regex::new(pattern)
+            .unwrap()
+            .captures(&shortstat)
+            .map_or(0, |cap| cap[1].parse().unwrap())
+    };
+    (
+        count(r"(\d+) files? changed"),
+        count(r"(\d+) insertions?"),
+        count(r"(\d+) deletions?"),
+    )
+}
+
+fn fixture() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.commit(
+        "S-10001 initial import",
+        &[("a.txt", Some("1\n2\n3\n")), ("b.txt", Some("x\ny\n"))],
+    );
+    repo.commit(
+        "S-10002 edit a and add c",
+        &[("a.txt", Some("1\ntwo\n3\n4\n")), ("c.txt", Some("new\n"))],
+    );
+    repo.commit("D-20001 remove b", &[("b.txt", None)]);
+    repo.commit(
+        "S-10003 rename a",
+        &[("a.txt", None), ("d.txt", Some("1\ntwo\n3\n4\n"))],
+    );
+    repo.commit("S-10002 finish", &[("c.txt", Some("new\nmore\nlines\n"))]);
+    repo
+}
+
+#[test]
+fn diffs_each_commit_against_its_parent() {
+    let repo = fixture();
+    let diff_collection = collect(repo.path(), &options()).unwrap();
+
+    assert_eq!(diff_collection.diffs.len(), 5);
+    // a renamed file is one file changed, with no lines added or removed
+    let rename = &diff_collection.diffs[1];
+    assert_eq!(
+        (rename.files_changed, rename.insertions, rename.deletions),
+        (1, 0, 0)
+    );
+    for diff_result in diff_collection.diffs.iter() {
+        let commit = repo
+            .repository
+            .find_commit(Oid::from_str(&diff_result.commit).unwrap())
+            .unwrap();
+        let parent = match commit.parent_count() {
+            0 => EMPTY_TREE.to_string(),
+            _ => commit.parent_id(0).unwrap().to_string(),
+        };
+
+        assert_eq!(
+            (
+                diff_result.files_changed,
+                diff_result.insertions,
+                diff_result.deletions
+            ),
+            git_shortstat(repo.dir.path(), &parent, &diff_result.commit),
+            "counts for '{}'",
+            diff_result.second_summary
+        );
+    }
+}
+
+#[test]
+fn counts_the_root_commit() {
+    let repo = fixture();
+    let diff_collection = collect(repo.path(), &options()).unwrap();
+
+    let root = diff_collection.diffs.last().unwrap();
+    assert_eq!(root.second_summary, "S-10001 initial import");
+    assert_eq!(root.first_summary, "");
+    assert_eq!(
+        (root.files_changed, root.insertions, root.deletions),
+        (2, 5, 0)
+    );
+}
+
+#[test]
+fn totals_sum_each_count() {
+    let repo = fixture();
+    let diff_collection = collect(repo.path(), &options()).unwrap();
+    let totals = total(diff_collection).unwrap().totals;
+
+    let story = &totals["S-10002"];
+    assert_eq!(story.total_diff_results, 2);
+    assert_eq!(
+        (story.files_changed, story.insertions, story.deletions),
+        (3, 5, 1)
+    );
+}
+
+#[test]
+fn parallel_collection_matches_sequential() {
+    let repo = fixture();
+    let parallel = CollectOptions {
+        jobs: 4,
+        ..options()
+    };
+
+    let sequential_json = serde_json::to_string(&collect(repo.path(), &options()).unwrap());
+    let parallel_json = serde_json::to_string(&collect(repo.path(), &parallel).unwrap());
+    assert_eq!(sequential_json.unwrap(), parallel_json.unwrap());
+}
+
+#[test]
+fn parallel_collection_keeps_walk_order_around_cached_diffs() {
+    let repo = fixture();
+    let cached = options().use_cache(true).jobs(4);
+    collect(repo.path(), &cached).unwrap();
+    repo.commit("S-10003 add d", &[("d.txt", Some("d\n"))]);
+    repo.commit("S-10003 edit d", &[("d.txt", Some("d\nd\n"))]);
+    repo.commit("S-10004 edit a", &[("a.txt", Some("a\n"))]);
+
+    let sequential_json = serde_json::to_string(&collect(repo.path(), &options()).unwrap());
+    let parallel_json = serde_json::to_string(&collect(repo.path(), &cached).unwrap());
+    assert_eq!(sequential_json.unwrap(), parallel_json.unwrap());
+}
+
+// Clones the fixture keeping only its last `depth` commits
+fn shallow_clone(repo: &TestRepo, depth: usize) -> TempDir {
+    let clone = TempDir::new().unwrap();
+    let status = Command::new("git")
+        .args(["clone", "--quiet", "--depth", &depth.to_string()])
+        .arg(format!("file://{}", repo.path()))
+        .arg(clone.path())
+        .status()
+        .expect("git must be installed to run these tests");
+    assert!(status.success());
+    clone
+}
+
+#[test]
+fn excludes_and_reports_shallow_boundaries() {
+    let repo = fixture();
+    let clone = shallow_clone(&repo, 2);
+    let boundary = repo.repository.revparse_single("HEAD~1").unwrap().id();
+
+    let collection = collect(clone.path().to_str().unwrap(), &options()).unwrap();
+
+    assert_eq!(collection.history_truncated_at, vec![boundary.to_string()]);
+    let commits: Vec<&str> = collection
+        .diffs
+        .iter()
+        .map(|diff_result| diff_result.second_summary.as_str())
+        .collect();
+    assert_eq!(commits, vec!["S-10002 finish"]);
+}
+
+#[test]
+fn flags_shallow_boundaries() {
+    let repo = fixture();
+    let clone = shallow_clone(&repo, 2);
+    let mut options = options();
+    options.shallow_boundary = ShallowBoundary::Flag;
+
+    let collection = collect(clone.path().to_str().unwrap(), &options).unwrap();
+
+    let flagged: Vec<(&str, bool)> = collection
+        .diffs
+        .iter()
+        .map(|diff_result| {
+            (
+                diff_result.second_summary.as_str(),
+                diff_result.shallow_boundary,
+            )
+        })
+        .collect();
+    assert_eq!(
+        flagged,
+        vec![("S-10002 finish", false), ("S-10003 rename a", true)]
+    );
+    assert_eq!(collection.history_truncated_at.len(), 1);
+}
+
+fn git(repo: &TestRepo, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo.path())
+        .args([
+            "-c",
+            "user.name=Tester",
+            "-c",
+            "user.email=tester@example.com",
+        ])
+        .args(args)
+        .output()
+        .expect("git must be installed to run these tests")
+        .status;
+    assert!(status.success());
+}
+
+// master: S-10001 -> D-20001 -> merge of a feature branch with S-10002 and S-10003
+fn merge_fixture() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.commit("S-10001 initial import", &[("a.txt", Some("1\n2\n"))]);
+    git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+    repo.commit("S-10002 add b", &[("b.txt", Some("b\n"))]);
+    repo.commit("S-10003 add c", &[("c.txt", Some("c\nc\n"))]);
+    git(&repo, &["checkout", "--quiet", "master"]);
+    repo.commit("D-20001 fix a", &[("a.txt", Some("1\ntwo\n"))]);
+    git(
+        &repo,
+        &[
+            "merge",
+            "--quiet",
+            "--no-ff",
+            "-m",
+            "Merge branch 'feature'",
+            "feature",
+        ],
+    );
+    repo
+}
+
+fn summaries(repo: &TestRepo, merge_mode: MergeMode) -> Vec<(String, Vec<String>)> {
+    let mut options = options();
+    options.merge_mode = merge_mode;
+
+    let mut summaries: Vec<(String, Vec<String>)> = collect(repo.path(), &options)
+        .unwrap()
+        .diffs
+        .into_iter()
+        .map(|diff_result| {
+            let mut story_number = diff_result.story_number;
+            story_number.sort();
+            (diff_result.second_summary, story_number)
+        })
+        .collect();
+    summaries.sort();
+    summaries
+}
+
+#[test]
+fn first_parent_mode_counts_only_the_merge() {
+    let repo = merge_fixture();
+
+    let summaries = summaries(&repo, MergeMode::FirstParent);
+
+    assert_eq!(
+        summaries,
+        vec![
+            ("D-20001 fix a".to_string(), vec!["D-20001".to_string()]),
+            ("Merge branch 'feature'".to_string(), vec![]),
+            (
+                "S-10001 initial import".to_string(),
+                vec!["S-10001".to_string()]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn all_commits_mode_counts_branch_commits_instead_of_merges() {
+    let repo = merge_fixture();
+
+    let summaries: Vec<String> = summaries(&repo, MergeMode::AllCommits)
+        .into_iter()
+        .map(|(summary, _)| summary)
+        .collect();
+
+    assert_eq!(
+        summaries,
+        vec![
+            "D-20001 fix a",
+            "S-10001 initial import",
+            "S-10002 add b",
+            "S-10003 add c"
+        ]
+    );
+}
+
+#[test]
+fn all_commits_mode_leaves_out_changes_made_in_merges() {
+    let repo = TestRepo::new();
+    repo.commit("S-10001 initial import", &[("a.txt", Some("1\n"))]);
+    git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+    repo.commit("S-10002 edit a", &[("a.txt", Some("feature\n"))]);
+    git(&repo, &["checkout", "--quiet", "master"]);
+    repo.commit("S-10003 edit a", &[("a.txt", Some("master\n"))]);
+    // a merge resolving both edits into new lines, as a conflict resolution would
+    git(
+        &repo,
+        &[
+            "merge",
+            "--quiet",
+            "--no-ff",
+            "--no-commit",
+            "-s",
+            "ours",
+            "feature",
+        ],
+    );
+    fs::write(repo.dir.path().join("a.txt"), "resolved\nextra\n").unwrap();
+    git(
+        &repo,
+        &["commit", "--quiet", "-am", "Merge branch 'feature'"],
+    );
+
+    let all_commits = collect(repo.path(), &options().merge_mode(MergeMode::AllCommits)).unwrap();
+    let first_parent = collect(repo.path(), &options()).unwrap();
+
+    assert_eq!(all_commits.diffs.len(), 3);
+    assert!(all_commits
+        .diffs
+        .iter()
+        .all(|diff_result| diff_result.second_summary != "Merge branch 'feature'"));
+    let resolution = &first_parent.diffs[0];
+    assert_eq!(resolution.second_summary, "Merge branch 'feature'");
+    assert_eq!((resolution.insertions, resolution.deletions), (2, 1));
+}
+
+#[test]
+fn merge_as_unit_mode_attributes_merges_to_branch_stories() {
+    let repo = merge_fixture();
+
+    let mut options = options();
+    options.merge_mode = MergeMode::MergeAsUnit;
+
+    let collection = collect(repo.path(), &options).unwrap();
+
+    let merge = &collection.diffs[0];
+    assert_eq!(merge.second_summary, "Merge branch 'feature'");
+    let mut story_number = merge.story_number.clone();
+    story_number.sort();
+    assert_eq!(story_number, vec!["S-10002", "S-10003"]);
+    assert_eq!(
+        (merge.files_changed, merge.insertions, merge.deletions),
+        (2, 3, 0)
+    );
+    assert_eq!(collection.diffs.len(), 3);
+}
+
+fn collected_summaries(repo: &TestRepo, options: &CollectOptions) -> Vec<String> {
+    collect(repo.path(), options)
+        .unwrap()
+        .diffs
+        .into_iter()
+        .map(|diff_result| diff_result.second_summary)
+        .collect()
+}
+
+#[test]
+fn excludes_reverted_pairs() {
+    let repo = fixture();
+    git(&repo, &["revert", "--no-edit", "HEAD"]);
+    let mut options = options();
+    options.reverts = Reverts::Exclude;
+
+    let collection = collect(repo.path(), &options).unwrap();
+
+    assert_eq!(collection.diffs.len(), 4);
+    assert!(collection
+        .diffs
+        .iter()
+        .all(|diff_result| !diff_result.second_summary.contains("finish")));
+}
+
+#[test]
+fn reverting_a_revert_counts_the_original() {
+    let repo = fixture();
+    git(&repo, &["revert", "--no-edit", "HEAD"]);
+    git(&repo, &["revert", "--no-edit", "HEAD"]);
+    let mut options = options();
+    options.reverts = Reverts::Exclude;
+
+    assert_eq!(
+        collected_summaries(&repo, &options),
+        vec![
+            "S-10002 finish",
+            "S-10003 rename a",
+            "D-20001 remove b",
+            "S-10002 edit a and add c",
+            "S-10001 initial import"
+        ]
+    );
+}
+
+#[test]
+fn dedupes_cherry_picks_of_collected_commits() {
+    let repo = TestRepo::new();
+    repo.commit("S-10001 initial import", &[("a.txt", Some("a\n"))]);
+    git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+    repo.commit("S-10002 add b", &[("b.txt", Some("b\n"))]);
+    git(&repo, &["checkout", "--quiet", "master"]);
+    git(&repo, &["cherry-pick", "-x", "feature"]);
+    git(
+        &repo,
+        &[
+            "merge",
+            "--quiet",
+            "--no-ff",
+            "-m",
+            "Merge branch 'feature'",
+            "feature",
+        ],
+    );
+    let mut options = options();
+    options.merge_mode = MergeMode::AllCommits;
+
+    assert_eq!(collected_summaries(&repo, &options).len(), 3);
+
+    options.cherry_picks = CherryPicks::Dedupe;
+    let mut summaries = collected_summaries(&repo, &options);
+    summaries.sort();
+    assert_eq!(summaries, vec!["S-10001 initial import", "S-10002 add b"]);
+}
+
+#[test]
+fn reports_missing_repos_and_branches() {
+    let repo = fixture();
+    let missing = TempDir::new().unwrap();
+
+    let error = collect(missing.path().to_str().unwrap(), &options()).unwrap_err();
+    assert_eq!(error.kind(), "repo_not_found");
+
+    let error = collect(repo.path(), &options().branch("nope")).unwrap_err();
+    assert_eq!(error.kind(), "branch_not_found");
+    assert_eq!(error.exit_code(), 4);
+}
+
+#[test]
+fn collect_each_emits_diffs_in_walk_order() {
+    let repo = fixture();
+    let mut streamed: Vec<String> = Vec::new();
+
+    let rest = collect_each(repo.path(), &options(), |diff_result| {
+        streamed.push(diff_result.commit);
+        Ok(())
+    })
+    .unwrap();
+
+    let collected: Vec<String> = collect(repo.path(), &options())
+        .unwrap()
+        .diffs
+        .into_iter()
+        .map(|diff_result| diff_result.commit)
+        .collect();
+    assert_eq!(streamed, collected);
+    assert!(rest.diffs.is_empty());
+}
+
+#[test]
+fn leaves_excluded_paths_out_of_counts() {
+    let repo = fixture();
+    let diff_collection = collect(repo.path(), &options().exclude_path("c.txt")).unwrap();
+    let totals = total(diff_collection).unwrap().totals;
+
+    let story = &totals["S-10002"];
+    assert_eq!(
+        (story.files_changed, story.insertions, story.deletions),
+        (1, 2, 1)
+    );
+}
+
+#[test]
+fn v1_matcher_finds_whole_story_numbers_only() {
+    let patterns = MessagePatterns::new(&options()).unwrap();
+
+    assert_eq!(
+        patterns.story_numbers("s-10345, D 10346 and e -10347: fix b-10348"),
+        vec!["S-10345", "D-10346", "E-10347", "B-10348"]
+    );
+    assert!(patterns
+        .story_numbers("Update 12345 rows in the release 20240 table")
+        .is_empty());
+    assert!(patterns.story_numbers("S-103456 and XS-10345").is_empty());
+}
+
+#[test]
+fn matches_stories_with_a_custom_pattern() {
+    let repo = TestRepo::new();
+    repo.commit("OPS-7 rotate keys", &[("a.txt", Some("1\n"))]);
+    repo.commit("S-10001 unrelated", &[("a.txt", Some("2\n"))]);
+
+    let options = options().matcher_pattern("ops", r"(OPS)-(\d+)");
+    let totals = total(collect(repo.path(), &options).unwrap())
+        .unwrap()
+        .totals;
+
+    let stories: Vec<&String> = totals.keys().collect();
+    assert_eq!(stories, vec!["OPS-7"]);
+}
+
+#[test]
+fn breaks_totals_down_by_repo() {
+    let api = fixture();
+    let web = TestRepo::new();
+    web.commit("S-10002 style the page", &[("page.css", Some("a\nb\n"))]);
+
+    let mut combined = collect(api.path(), &options().repo_name("api")).unwrap();
+    combined.append(collect(web.path(), &options().repo_name("web")).unwrap());
+    let totals = total(combined).unwrap().totals;
+
+    let story = &totals["S-10002"];
+    assert_eq!(story.total_diff_results, 3);
+    assert_eq!(story.repos["api"].insertions, 5);
+    assert_eq!(story.repos["web"].insertions, 2);
+    assert!(totals["D-20001"].repos.contains_key("api"));
+}
+
+// app: S-10001 -> S-10002 adds lib as a submodule -> S-10003 moves lib on by one commit
+fn submodule_fixture() -> (TestRepo, TestRepo) {
+    let lib = TestRepo::new();
+    lib.commit("L-1 start", &[("lib.txt", Some("1\n"))]);
+
+    let app = TestRepo::new();
+    app.commit("S-10001 initial import", &[("app.txt", Some("app\n"))]);
+    git(
+        &app,
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            "--quiet",
+            lib.path(),
+            "lib",
+        ],
+    );
+    git(&app, &["commit", "--quiet", "-m", "S-10002 add lib"]);
+
+    lib.commit("L-2 grow", &[("lib.txt", Some("1\n2\n3\n"))]);
+    git(
+        &app,
+        &[
+            "-C",
+            "lib",
+            "pull",
+            "--quiet",
+            "--ff-only",
+            "origin",
+            "master",
+        ],
+    );
+    git(&app, &["commit", "--quiet", "-am", "S-10003 bump lib"]);
+
+    (app, lib)
+}
+
+#[test]
+fn counts_submodule_commit_ranges() {
+    let (app, _lib) = submodule_fixture();
+
+    let plain = total(collect(app.path(), &options()).unwrap()).unwrap();
+    let collection = collect(app.path(), &options().submodules(true)).unwrap();
+
+    let bump = &collection.diffs[0];
+    assert_eq!(bump.second_summary, "S-10003 bump lib");
+    assert_eq!(bump.submodules.len(), 1);
+    assert_eq!(bump.submodules[0].path, "lib");
+    assert_eq!(
+        (bump.submodules[0].insertions, bump.submodules[0].deletions),
+        (2, 0)
+    );
+    assert_eq!(bump.insertions, plain.totals["S-10003"].insertions + 2);
+    // adding the submodule doesn't count its whole tree
+    assert!(collection.diffs[1].submodules.is_empty());
+}
+
+#[test]
+fn leaves_out_submodules_that_are_not_initialized() {
+    let (app, _lib) = submodule_fixture();
+    git(&app, &["submodule", "deinit", "--quiet", "--force", "lib"]);
+    fs::remove_dir_all(app.repository.path().join("modules").join("lib")).unwrap();
+    let plain = collect(app.path(), &options()).unwrap();
+
+    let collection = collect(app.path(), &options().submodules(true)).unwrap();
+
+    let bump = &collection.diffs[0];
+    assert!(bump.submodules.is_empty());
+    assert_eq!(bump.skipped_submodules.len(), 1);
+    assert_eq!(bump.skipped_submodules[0].path, "lib");
+    assert!(bump.skipped_submodules[0]
+        .reason
+        .starts_with("not initialized"));
+    assert_eq!(bump.insertions, plain.diffs[0].insertions);
+    assert!(collection.skipped_commits.is_empty());
+    assert_eq!(total(collection).unwrap().skipped_submodules, 1);
+}
+
+#[test]
+fn totals_track_story_lifecycles() {
+    const DAY: i64 = 86_400;
+    let diff_result = |story_number: &str, timestamp: Option<i64>| DiffResult {
+        story_number: vec![story_number.to_string()],
+        timestamp,
+        ..DiffResult::default()
+    };
+    let diff_collection = DiffCollection {
+        diffs: vec![
+            diff_result("S-10001", Some(10 * DAY + 60)),
+            diff_result("S-10001", Some(3 * DAY)),
+            diff_result("S-10001", Some(3 * DAY + 600)),
+            diff_result("S-10002", None),
+        ],
+        ..DiffCollection::default()
+    };
+    let totals = total(diff_collection).unwrap().totals;
+
+    let story = &totals["S-10001"];
+    assert_eq!(story.first_commit_at, Some(3 * DAY));
+    assert_eq!(story.last_commit_at, Some(10 * DAY + 60));
+    assert_eq!(story.cycle_time_seconds, Some(7 * DAY + 60));
+    assert_eq!(story.active_days, 2);
+    assert_eq!(totals["S-10002"].first_commit_at, None);
+}
+
+#[test]
+fn filters_commits_by_author() {
+    let repo = fixture();
+    git(
+        &repo,
+        &[
+            "commit",
+            "--quiet",
+            "--allow-empty",
+            "--author",
+            "dependabot[bot] <49699333+dependabot[bot]@users.noreply.github.com>",
+            "-m",
+            "S-10003 bump dependencies",
+        ],
+    );
+
+    let without_bots = collect(repo.path(), &options().exclude_bots(true)).unwrap();
+    assert_eq!(without_bots.diffs.len(), 5);
+    assert_eq!(without_bots.excluded_by_author, 1);
+
+    // the bot's commit was committed by Tester, so it's kept for either of them
+    let by_domain = options().author("@users.noreply.GITHUB.com");
+    assert_eq!(
+        collected_summaries(&repo, &by_domain),
+        vec!["S-10003 bump dependencies"]
+    );
+    let by_name = options().exclude_author("^Tester ");
+    assert_eq!(
+        collect(repo.path(), &by_name).unwrap().excluded_by_author,
+        6
+    );
+}
+
+#[test]
+fn totals_aliased_stories_under_their_canonical_number() {
+    let repo = fixture();
+    repo.commit(
+        "b-30001 rename a, after B 30001 was E-40001",
+        &[("a.txt", Some("1\ntwo\n3\n4\n5\n"))],
+    );
+    let aliases = StoryAliasCollection {
+        aliases: vec![StoryAlias {
+            alias: "b 30001".to_string(),
+            story_number: "S-10002".to_string(),
+        }],
+    };
+
+    let diff_collection = collect(repo.path(), &options()).unwrap();
+    let renamed = &diff_collection.diffs[0];
+    assert_eq!(renamed.story_number, vec!["B-30001", "E-40001"]);
+
+    let mut accumulator = TotalAccumulator::new().with_aliases(aliases.index());
+    accumulator.add_collection(diff_collection);
+    let totals = accumulator.finish().totals;
+
+    assert!(!totals.contains_key("B-30001"));
+    assert_eq!(totals["S-10002"].total_diff_results, 3);
+    assert_eq!(totals["E-40001"].total_diff_results, 1);
+}
+
+// Rewrites the cached diff of `commit`, so a run that reads it shows where its counts came from
+fn tamper_with_cache(repo: &TestRepo, options: &CollectOptions, commit: &str) {
+    let git_dir = repo.repository.path();
+    let mut cache = DiffCache::load(git_dir);
+    let mut diff_result = cache.get(&options.cache_key(), commit).unwrap().clone();
+    diff_result.insertions = 999;
+    cache.insert(&options.cache_key(), commit.to_string(), diff_result);
+    cache.save(git_dir).unwrap();
+}
+
+#[test]
+fn reuses_cached_diffs() {
+    let repo = fixture();
+    let cached = options().use_cache(true);
+    let head = collect(repo.path(), &cached).unwrap().diffs[0]
+        .commit
+        .clone();
+    tamper_with_cache(&repo, &cached, &head);
+    let cache_path = get_cache_path(repo.repository.path());
+    let saved_at = fs::metadata(&cache_path).unwrap().modified().unwrap();
+
+    let diff_collection = collect(repo.path(), &cached).unwrap();
+
+    assert_eq!(diff_collection.diffs[0].insertions, 999);
+    // nothing new was diffed, so the cache wasn't written again
+    assert_eq!(
+        fs::metadata(&cache_path).unwrap().modified().unwrap(),
+        saved_at
+    );
+    assert_eq!(
+        collect(repo.path(), &options()).unwrap().diffs[0].insertions,
+        2
+    );
+}
+
+#[test]
+fn caches_diffs_for_each_set_of_options() {
+    let repo = fixture();
+    let cached = options().use_cache(true);
+    let head = collect(repo.path(), &cached).unwrap().diffs[0]
+        .commit
+        .clone();
+    tamper_with_cache(&repo, &cached, &head);
+
+    for changed in [
+        cached.clone().matcher("jira"),
+        cached.clone().exclude_path("*.lock"),
+        cached.clone().submodules(true),
+    ] {
+        assert_ne!(changed.cache_key(), cached.cache_key());
+        let diff_collection = collect(repo.path(), &changed).unwrap();
+        assert_eq!(diff_collection.diffs[0].insertions, 2);
+    }
+}
+
+#[test]
+fn leaves_the_repo_alone_unless_caching() {
+    let repo = fixture();
+    let cache_path = get_cache_path(repo.repository.path());
+
+    collect(repo.path(), &CollectOptions::new().jobs(1)).unwrap();
+    assert!(!cache_path.exists());
+
+    collect(repo.path(), &options().use_cache(true)).unwrap();
+    assert!(cache_path.exists());
+    assert!(clear_cache(repo.path()).unwrap());
+    assert!(!cache_path.exists());
+    assert!(!clear_cache(repo.path()).unwrap());
+}
+
+#[test]
+fn collects_even_if_the_cache_cant_be_saved() {
+    let repo = fixture();
+    // a file where the cache directory should be
+    let cache_path = get_cache_path(repo.repository.path());
+    fs::write(cache_path.parent().unwrap(), "").unwrap();
+
+    let diff_collection = collect(repo.path(), &options().use_cache(true)).unwrap();
+
+    assert_eq!(diff_collection.diffs.len(), 5);
+    assert!(!cache_path.exists());
+}
+
+// A repo whose third commit added a file whose contents have since gone missing, like an object a
+// partial clone never fetched
+fn missing_object_fixture() -> (TestRepo, Oid) {
+    let repo = fixture();
+    let broken = repo.commit("S-10003 add lost", &[("lost.txt", Some("lost\n"))]);
+    repo.commit("S-10004 edit a", &[("a.txt", Some("1\n"))]);
+
+    let blob = repo
+        .repository
+        .find_commit(broken)
+        .unwrap()
+        .tree()
+        .unwrap()
+        .get_name("lost.txt")
+        .unwrap()
+        .id()
+        .to_string();
+    let object_path = repo
+        .repository
+        .path()
+        .join("objects")
+        .join(&blob[..2])
+        .join(&blob[2..]);
+    fs::remove_file(object_path).unwrap();
+
+    (repo, broken)
+}
+
+#[test]
+fn fails_on_unreadable_commits() {
+    let (repo, broken) = missing_object_fixture();
+
+    for jobs in [1, 4] {
+        match collect(repo.path(), &options().jobs(jobs)) {
+            Err(CliError::Commit(error)) => assert_eq!(error.commit, Some(broken.to_string())),
+            other => panic!("expected a commit error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn skips_unreadable_commits_when_asked() {
+    let (repo, broken) = missing_object_fixture();
+
+    for jobs in [1, 4] {
+        let diff_collection =
+            collect(repo.path(), &options().skip_errors(true).jobs(jobs)).unwrap();
+
+        assert_eq!(diff_collection.skipped_commits.len(), 1);
+        assert_eq!(
+            diff_collection.skipped_commits[0].commit,
+            Some(broken.to_string())
+        );
+        assert_eq!(diff_collection.diffs.len(), 6);
+        assert_eq!(total(diff_collection).unwrap().skipped_commits, 1);
+    }
+}
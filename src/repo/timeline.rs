@@ -0,0 +1,501 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fmt::Formatter;
+
+use crate::repo::aliases::StoryAliasIndex;
+use crate::repo::diff::{
+    format_points, is_zero, write_counts, DiffCollection, DiffResult, Pointable, StoryPointIndex,
+};
+use crate::repo::options::Period;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A named range of dates, both included, e.g.
+/// `{"name": "Sprint 12", "start": "2024-03-04", "end": "2024-03-15"}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sprint {
+    pub name: String,
+    pub start: String,
+    pub end: String,
+}
+
+/// A sprint calendar file, shaped like `{"sprints": [...]}`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SprintCalendar {
+    pub sprints: Vec<Sprint>,
+}
+
+// A period's name and the dates it covers, both included
+#[derive(Debug, Clone)]
+struct DateRange {
+    name: String,
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+/// The periods a timeline buckets commits into: calendar days, weeks or months, or the sprints
+/// of a calendar. Calendar periods are in UTC, and weeks start on Monday.
+#[derive(Debug, Clone)]
+pub struct Periods {
+    // None for the periods of a sprint calendar
+    period: Option<Period>,
+    sprints: Vec<DateRange>,
+}
+
+impl Periods {
+    pub fn calendar(period: Period) -> Periods {
+        Periods {
+            period: Some(period),
+            sprints: Vec::new(),
+        }
+    }
+
+    /// The sprints of a calendar, which must not overlap.
+    pub fn sprints(calendar: &SprintCalendar) -> Result<Periods, String> {
+        let mut sprints = Vec::with_capacity(calendar.sprints.len());
+        for sprint in calendar.sprints.iter() {
+            let parse_date = |date: &str| {
+                NaiveDate::parse_from_str(date, DATE_FORMAT).map_err(|error| {
+                    format!(
+                        "sprint '{}' has an invalid date '{}': {}",
+                        sprint.name, date, error
+                    )
+                })
+            };
+            let (start, end) = (parse_date(&sprint.start)?, parse_date(&sprint.end)?);
+            if end < start {
+                return Err(format!("sprint '{}' ends before it starts", sprint.name));
+            }
+
+            sprints.push(DateRange {
+                name: sprint.name.to_string(),
+                start,
+                end,
+            });
+        }
+
+        sprints.sort_by_key(|sprint| sprint.start);
+        for pair in sprints.windows(2) {
+            if pair[1].start <= pair[0].end {
+                return Err(format!(
+                    "sprints '{}' and '{}' overlap",
+                    pair[0].name, pair[1].name
+                ));
+            }
+        }
+
+        Ok(Periods {
+            period: None,
+            sprints,
+        })
+    }
+
+    // The period a date falls in, or None for a date outside every sprint
+    fn get_range(&self, date: NaiveDate) -> Option<DateRange> {
+        match self.period {
+            Some(Period::Day) => Some(DateRange {
+                name: date.format(DATE_FORMAT).to_string(),
+                start: date,
+                end: date,
+            }),
+            Some(Period::Week) => {
+                // e.g. '2024-W03', by ISO week number
+                let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                let week = date.iso_week();
+                Some(DateRange {
+                    name: format!("{}-W{:02}", week.year(), week.week()),
+                    start,
+                    end: start + Duration::days(6),
+                })
+            }
+            Some(Period::Month) => {
+                let start = date.with_day(1)?;
+                let next_month = match start.month() {
+                    12 => NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?,
+                    month => NaiveDate::from_ymd_opt(start.year(), month + 1, 1)?,
+                };
+                Some(DateRange {
+                    name: date.format("%Y-%m").to_string(),
+                    start,
+                    end: next_month - Duration::days(1),
+                })
+            }
+            None => self
+                .sprints
+                .iter()
+                .find(|sprint| sprint.start <= date && date <= sprint.end)
+                .cloned(),
+        }
+    }
+
+    // Every sprint in the calendar, or every calendar period from `first` to `last`, so periods
+    // without commits are listed too
+    fn get_ranges(&self, first: NaiveDate, last: NaiveDate) -> Vec<DateRange> {
+        if self.period.is_none() {
+            return self.sprints.clone();
+        }
+
+        let mut ranges = Vec::new();
+        let mut date = first;
+        while let Some(range) = self.get_range(date).filter(|_| date <= last) {
+            date = range.end + Duration::days(1);
+            ranges.push(range);
+        }
+        ranges
+    }
+}
+
+/// A period's counts, overall and for each story committed to in it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PeriodTotal {
+    pub period: String,
+    pub start: String,
+    pub end: String,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub total_diff_results: usize,
+    /// The points of the stories whose last commit falls in this period.
+    pub points_completed: String,
+    pub stories: BTreeMap<String, StoryPeriodTotal>,
+}
+
+impl PeriodTotal {
+    fn new(range: &DateRange) -> PeriodTotal {
+        PeriodTotal {
+            period: range.name.to_string(),
+            start: range.start.format(DATE_FORMAT).to_string(),
+            end: range.end.format(DATE_FORMAT).to_string(),
+            files_changed: 0,
+            insertions: 0,
+            deletions: 0,
+            total_diff_results: 0,
+            points_completed: "0".to_string(),
+            stories: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, diff_result: &DiffResult) {
+        self.files_changed += diff_result.files_changed;
+        self.insertions += diff_result.insertions;
+        self.deletions += diff_result.deletions;
+        self.total_diff_results += 1;
+
+        for story_number in diff_result.story_number.iter() {
+            let story = self.stories.entry(story_number.to_string()).or_default();
+            story.files_changed += diff_result.files_changed;
+            story.insertions += diff_result.insertions;
+            story.deletions += diff_result.deletions;
+            story.total_diff_results += 1;
+        }
+    }
+}
+
+impl fmt::Display for PeriodTotal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ({} to {}) :: ", self.period, self.start, self.end)?;
+        write_counts(
+            f,
+            self.files_changed,
+            self.insertions,
+            self.deletions,
+            self.total_diff_results,
+        )?;
+        write!(f, ", {}sp completed", self.points_completed)?;
+
+        for (story_number, story) in self.stories.iter() {
+            write!(f, "\n    {} :: {}", story_number, story)?;
+        }
+        Ok(())
+    }
+}
+
+/// A story's counts within one period.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct StoryPeriodTotal {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub total_diff_results: usize,
+    /// The story's points, set in the period of its last commit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_completed: Option<String>,
+}
+
+impl fmt::Display for StoryPeriodTotal {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_counts(
+            f,
+            self.files_changed,
+            self.insertions,
+            self.deletions,
+            self.total_diff_results,
+        )?;
+        if let Some(points) = &self.points_completed {
+            write!(f, ", {}sp completed", points)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Timeline {
+    pub periods: Vec<PeriodTotal>,
+    /// Commits with no timestamp, from collections written by older versions.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub undated_commits: usize,
+    /// Commits made outside every sprint of the calendar.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub unscheduled_commits: usize,
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for period in self.periods.iter() {
+            writeln!(f, "{}", period)?;
+        }
+
+        if self.undated_commits > 0 {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{} commits have no date and are not counted.",
+                self.undated_commits
+            )?;
+        }
+        if self.unscheduled_commits > 0 {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "{} commits fall outside every sprint and are not counted.",
+                self.unscheduled_commits
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// A story's points are completed in the last period it has commits in
+impl Pointable for Timeline {
+    fn add_points(&mut self, points_index: &StoryPointIndex) -> &mut Self {
+        let mut last_periods: HashMap<String, usize> = HashMap::new();
+        for (index, period) in self.periods.iter().enumerate() {
+            for story_number in period.stories.keys() {
+                last_periods.insert(story_number.to_string(), index);
+            }
+        }
+
+        for (story_number, index) in last_periods {
+            if let Some(points) = points_index.get(&story_number) {
+                if let Some(story) = self.periods[index].stories.get_mut(&story_number) {
+                    story.points_completed = Some(points.to_string());
+                }
+            }
+        }
+
+        for period in self.periods.iter_mut() {
+            let points: f64 = period
+                .stories
+                .values()
+                .filter_map(|story| story.points_completed.as_ref())
+                .filter_map(|points| points.parse::<f64>().ok())
+                .sum();
+            period.points_completed = format_points(points);
+        }
+
+        self
+    }
+}
+
+/// Buckets diff results into periods one at a time, so a stream of them never has to be held
+/// in memory.
+#[derive(Debug)]
+pub struct TimelineAccumulator {
+    periods: Periods,
+    aliases: StoryAliasIndex,
+    totals: BTreeMap<NaiveDate, PeriodTotal>,
+    last_date: Option<NaiveDate>,
+    undated_commits: usize,
+    unscheduled_commits: usize,
+}
+
+impl TimelineAccumulator {
+    pub fn new(periods: Periods) -> TimelineAccumulator {
+        TimelineAccumulator {
+            periods,
+            aliases: StoryAliasIndex::default(),
+            totals: BTreeMap::new(),
+            last_date: None,
+            undated_commits: 0,
+            unscheduled_commits: 0,
+        }
+    }
+
+    /// Buckets each story under its canonical story number.
+    pub fn with_aliases(mut self, aliases: StoryAliasIndex) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    pub fn add(&mut self, diff_result: &DiffResult) {
+        let date = match diff_result
+            .timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        {
+            Some(time) => time.date_naive(),
+            None => {
+                self.undated_commits += 1;
+                return;
+            }
+        };
+        let range = match self.periods.get_range(date) {
+            Some(range) => range,
+            None => {
+                self.unscheduled_commits += 1;
+                return;
+            }
+        };
+
+        self.last_date = self.last_date.max(Some(date));
+        let period_total = self
+            .totals
+            .entry(range.start)
+            .or_insert_with(|| PeriodTotal::new(&range));
+        if self.aliases.is_empty() {
+            period_total.add(diff_result);
+        } else {
+            let mut diff_result = diff_result.clone();
+            self.aliases.apply(&mut diff_result);
+            period_total.add(&diff_result);
+        }
+    }
+
+    pub fn add_collection(&mut self, diff_collection: &DiffCollection) {
+        for diff_result in diff_collection.diffs.iter() {
+            self.add(diff_result);
+        }
+    }
+
+    pub fn finish(mut self) -> Timeline {
+        let first_date = self.totals.keys().next().copied();
+        if let (Some(first), Some(last)) = (first_date, self.last_date) {
+            for range in self.periods.get_ranges(first, last) {
+                self.totals
+                    .entry(range.start)
+                    .or_insert_with(|| PeriodTotal::new(&range));
+            }
+        }
+
+        Timeline {
+            periods: self.totals.into_values().collect(),
+            undated_commits: self.undated_commits,
+            unscheduled_commits: self.unscheduled_commits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::diff::{StoryPoint, StoryPointCollection};
+
+    // 2024-01-01 was a Monday
+    const MONDAY: i64 = 1_704_067_200;
+    const DAY: i64 = 86_400;
+
+    fn diff_result(story_number: &str, timestamp: i64, insertions: usize) -> DiffResult {
+        DiffResult {
+            story_number: vec![story_number.to_string()],
+            insertions,
+            timestamp: Some(timestamp),
+            ..DiffResult::default()
+        }
+    }
+
+    fn timeline(periods: Periods, diff_results: &[DiffResult]) -> Timeline {
+        let mut accumulator = TimelineAccumulator::new(periods);
+        for diff_result in diff_results {
+            accumulator.add(diff_result);
+        }
+        accumulator.finish()
+    }
+
+    #[test]
+    fn buckets_by_week_and_lists_empty_weeks() {
+        let timeline = timeline(
+            Periods::calendar(Period::Week),
+            &[
+                diff_result("S-10001", MONDAY + 2 * DAY, 3),
+                diff_result("S-10001", MONDAY + 6 * DAY, 4),
+                diff_result("S-10002", MONDAY + 15 * DAY, 5),
+            ],
+        );
+
+        let periods: Vec<(&str, usize)> = timeline
+            .periods
+            .iter()
+            .map(|period| (period.period.as_str(), period.insertions))
+            .collect();
+        assert_eq!(
+            periods,
+            vec![("2024-W01", 7), ("2024-W02", 0), ("2024-W03", 5)]
+        );
+        assert_eq!(timeline.periods[0].end, "2024-01-07");
+    }
+
+    #[test]
+    fn completes_points_in_the_last_sprint_with_commits() {
+        let calendar = SprintCalendar {
+            sprints: vec![
+                Sprint {
+                    name: "Sprint 1".to_string(),
+                    start: "2024-01-01".to_string(),
+                    end: "2024-01-07".to_string(),
+                },
+                Sprint {
+                    name: "Sprint 2".to_string(),
+                    start: "2024-01-08".to_string(),
+                    end: "2024-01-14".to_string(),
+                },
+            ],
+        };
+        let mut timeline = timeline(
+            Periods::sprints(&calendar).unwrap(),
+            &[
+                diff_result("S-10001", MONDAY, 1),
+                diff_result("S-10001", MONDAY + 8 * DAY, 1),
+                diff_result("S-10002", MONDAY + 30 * DAY, 1),
+            ],
+        );
+        let points = StoryPointCollection {
+            story_points: vec![StoryPoint {
+                story_number: "S-10001".to_string(),
+                points: "5".to_string(),
+            }],
+        };
+        timeline.add_points(&points.index());
+
+        assert_eq!(timeline.periods[0].points_completed, "0");
+        assert_eq!(timeline.periods[1].points_completed, "5");
+        assert_eq!(timeline.unscheduled_commits, 1);
+    }
+
+    #[test]
+    fn rejects_overlapping_sprints() {
+        let sprint = |name: &str, start: &str, end: &str| Sprint {
+            name: name.to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+        };
+        let calendar = SprintCalendar {
+            sprints: vec![
+                sprint("Sprint 1", "2024-01-01", "2024-01-10"),
+                sprint("Sprint 2", "2024-01-08", "2024-01-14"),
+            ],
+        };
+
+        assert!(Periods::sprints(&calendar).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::errors::TrackerError;
+use crate::repo::diff::{StoryPoint, StoryPointCollection};
+use crate::tracker::{
+    format_points, get_agent, get_json, select_requested, TrackerClient, BATCH_SIZE,
+};
+
+// The story points field on a default Jira Software install
+const DEFAULT_POINTS_FIELD: &str = "customfield_10002";
+
+// Jira Cloud has retired /rest/api/2/search for this one, which Jira Server and Data Center lack
+const CLOUD_SEARCH_PATH: &str = "/rest/api/3/search/jql";
+const SERVER_SEARCH_PATH: &str = "/rest/api/2/search";
+
+/// Fetches points through the Jira REST search API: Jira Cloud's when a user is given, since it
+/// authenticates with a user and API token, and Jira Server's otherwise.
+pub struct JiraClient {
+    pub base_url: String,
+    pub user: Option<String>,
+    pub token: Option<String>,
+    pub points_field: String,
+    agent: ureq::Agent,
+}
+
+impl JiraClient {
+    pub fn new(base_url: &str, user: Option<String>, token: Option<String>) -> JiraClient {
+        JiraClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user,
+            token,
+            points_field: DEFAULT_POINTS_FIELD.to_string(),
+            agent: get_agent(),
+        }
+    }
+
+    // Jira Cloud expects basic auth with an API token, Jira Server accepts a bearer token
+    fn authorization(&self) -> Option<String> {
+        match (&self.user, &self.token) {
+            (Some(user), Some(token)) => Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{}:{}", user, token))
+            )),
+            (None, Some(token)) => Some(format!("Bearer {}", token)),
+            _ => None,
+        }
+    }
+
+    fn fetch_batch(&self, story_numbers: &[String]) -> Result<Vec<StoryPoint>, TrackerError> {
+        let keys = story_numbers
+            .iter()
+            .map(|story_number| format!("\"{}\"", story_number))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let search_path = match self.user {
+            Some(_) => CLOUD_SEARCH_PATH,
+            None => SERVER_SEARCH_PATH,
+        };
+        let mut request = self
+            .agent
+            .get(&format!("{}{}", self.base_url, search_path))
+            .query("jql", &format!("key in ({})", keys))
+            .query("fields", &self.points_field)
+            .query("maxResults", &story_numbers.len().to_string())
+            .set("Accept", "application/json");
+        if search_path == SERVER_SEARCH_PATH {
+            // unknown keys are reported as warnings instead of failing the whole query
+            request = request.query("validateQuery", "warn");
+        }
+        if let Some(authorization) = self.authorization() {
+            request = request.set("Authorization", &authorization);
+        }
+
+        let body = get_json(request)?;
+        let issues = body["issues"].as_array().cloned().unwrap_or_default();
+
+        Ok(issues
+            .iter()
+            .filter_map(|issue| {
+                let key = issue["key"].as_str()?;
+                let points = format_points(&issue["fields"][&self.points_field])?;
+                Some(StoryPoint {
+                    story_number: key.to_string(),
+                    points,
+                })
+            })
+            .collect())
+    }
+}
+
+impl TrackerClient for JiraClient {
+    fn fetch_points(&self, story_numbers: &[String]) -> Result<StoryPointCollection, TrackerError> {
+        let mut found = Vec::new();
+        for batch in story_numbers.chunks(BATCH_SIZE) {
+            found.extend(self.fetch_batch(batch)?);
+        }
+
+        Ok(select_requested(story_numbers, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::test_server::TestServer;
+
+    #[test]
+    fn fetches_points_for_requested_keys() {
+        let server = TestServer::start(vec![(
+            200,
+            r#"{"issues": [
+                {"key": "SO-1", "fields": {"customfield_10002": 5.0}},
+                {"key": "SO-2", "fields": {"customfield_10002": null}}
+            ]}"#,
+        )]);
+
+        let client = JiraClient::new(&server.url, None, Some("secret".to_string()));
+        let points = client
+            .fetch_points(&["SO-1".to_string(), "SO-2".to_string()])
+            .unwrap();
+
+        assert_eq!(points.story_points.len(), 1);
+        assert_eq!(points.story_points[0].story_number, "SO-1");
+        assert_eq!(points.story_points[0].points, "5");
+
+        let requests = server.requests();
+        assert!(requests[0]
+            .starts_with("GET /rest/api/2/search?jql=key+in+%28%22SO-1%22%2C%22SO-2%22%29"));
+        assert!(requests[0].contains("fields=customfield_10002"));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+    }
+
+    #[test]
+    fn uses_cloud_search_and_basic_auth_with_a_user() {
+        let server = TestServer::start(vec![(200, r#"{"issues": []}"#)]);
+
+        let client = JiraClient::new(
+            &server.url,
+            Some("me@example.com".to_string()),
+            Some("token".to_string()),
+        );
+        client.fetch_points(&["SO-1".to_string()]).unwrap();
+
+        let expected = format!(
+            "authorization: basic {}",
+            STANDARD.encode("me@example.com:token")
+        );
+        let requests = server.requests();
+        assert!(requests[0].starts_with("GET /rest/api/3/search/jql?jql="));
+        assert!(!requests[0].contains("validateQuery"));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains(&expected.to_lowercase()));
+    }
+
+    #[test]
+    fn reports_http_errors() {
+        let server = TestServer::start(vec![(401, r#"{"errorMessages": ["Unauthorized"]}"#)]);
+
+        let client = JiraClient::new(&server.url, None, None);
+        let error = client.fetch_points(&["SO-1".to_string()]).err().unwrap();
+
+        assert!(error.to_string().contains("HTTP 401"));
+    }
+}
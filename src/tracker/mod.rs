@@ -0,0 +1,197 @@
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::errors::TrackerError;
+use crate::repo::diff::{normalize_story_number, StoryPoint, StoryPointCollection};
+use crate::tracker::jira::JiraClient;
+use crate::tracker::version_one::VersionOneClient;
+
+pub mod jira;
+pub mod version_one;
+
+#[cfg(test)]
+mod test_server;
+
+// Most trackers cap the size of a query, so stories are requested in batches
+const BATCH_SIZE: usize = 50;
+const TIMEOUT_SECONDS: u64 = 30;
+
+/// Looks up story points from an issue tracker.
+pub trait TrackerClient {
+    /// Fetches the points for the given stories. Stories the tracker doesn't know, or that
+    /// have no estimate, are left out of the result.
+    fn fetch_points(&self, story_numbers: &[String]) -> Result<StoryPointCollection, TrackerError>;
+}
+
+#[derive(Clone)]
+pub struct TrackerConfig {
+    pub kind: String,
+    pub url: String,
+    pub user: Option<String>,
+    pub token: Option<String>,
+    pub points_field: Option<String>,
+}
+
+pub fn get_client(config: &TrackerConfig) -> Result<Box<dyn TrackerClient>, TrackerError> {
+    let TrackerConfig {
+        kind,
+        url,
+        user,
+        token,
+        points_field,
+    } = config;
+
+    match kind.as_str() {
+        "jira" => {
+            let mut client = JiraClient::new(url, user.clone(), token.clone());
+            if let Some(points_field) = points_field {
+                client.points_field = points_field.to_string();
+            }
+            Ok(Box::new(client))
+        }
+        "v1" => Ok(Box::new(VersionOneClient::new(url, token.clone()))),
+        "file" => Ok(Box::new(FileTrackerClient::new(url))),
+        _ => Err(TrackerError::from(format!("Unknown tracker '{}'.", kind))),
+    }
+}
+
+/// Serves points from a local points file, for offline use and tests.
+pub struct FileTrackerClient {
+    pub path: PathBuf,
+}
+
+impl FileTrackerClient {
+    pub fn new(path: &str) -> FileTrackerClient {
+        FileTrackerClient {
+            path: PathBuf::from(path),
+        }
+    }
+}
+
+impl TrackerClient for FileTrackerClient {
+    fn fetch_points(&self, story_numbers: &[String]) -> Result<StoryPointCollection, TrackerError> {
+        let file = File::open(&self.path).map_err(|error| {
+            TrackerError::from(format!("Could not open {}: {}", self.path.display(), error))
+        })?;
+        let points_collection: StoryPointCollection = serde_json::from_reader(BufReader::new(file))
+            .map_err(|error| {
+                TrackerError::from(format!("Could not read {}: {}", self.path.display(), error))
+            })?;
+        let points_index = points_collection.index();
+
+        let story_points = story_numbers
+            .iter()
+            .filter_map(|story_number| {
+                points_index.get(story_number).map(|points| StoryPoint {
+                    story_number: story_number.to_string(),
+                    points: points.to_string(),
+                })
+            })
+            .collect();
+
+        Ok(StoryPointCollection { story_points })
+    }
+}
+
+fn get_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+        .build()
+}
+
+// Sends a GET request and parses the JSON body, turning HTTP failures into TrackerErrors
+fn get_json(request: ureq::Request) -> Result<Value, TrackerError> {
+    let url = request.url().to_string();
+    let response = request.call().map_err(|error| match error {
+        ureq::Error::Status(status, _) => {
+            TrackerError::from(format!("{} responded with HTTP {}.", url, status))
+        }
+        ureq::Error::Transport(transport) => {
+            TrackerError::from(format!("Could not reach the tracker: {}", transport))
+        }
+    })?;
+
+    response
+        .into_json()
+        .map_err(|error| TrackerError::from(format!("Invalid response from {}: {}", url, error)))
+}
+
+// Trackers report estimates as numbers, '5.0' is written as '5' to match points files
+fn format_points(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(number) => match number.as_f64() {
+            Some(points) if points.fract() == 0.0 => Some(format!("{}", points as i64)),
+            Some(points) => Some(points.to_string()),
+            None => Some(number.to_string()),
+        },
+        Value::String(points) if !points.is_empty() => Some(points.to_string()),
+        _ => None,
+    }
+}
+
+// Keeps only the requested stories, using the caller's spelling of each story number
+fn select_requested(story_numbers: &[String], found: Vec<StoryPoint>) -> StoryPointCollection {
+    let mut story_points = Vec::new();
+
+    for story_point in found {
+        let normalized = normalize_story_number(&story_point.story_number);
+        let requested = story_numbers
+            .iter()
+            .find(|story_number| normalize_story_number(story_number) == normalized);
+
+        if let Some(story_number) = requested {
+            story_points.push(StoryPoint {
+                story_number: story_number.to_string(),
+                points: story_point.points,
+            });
+        }
+    }
+
+    StoryPointCollection { story_points }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn file_client_returns_only_requested_stories() {
+        let path = std::env::temp_dir().join(format!("slc-tracker-{}.json", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        write!(
+            file,
+            r#"{{"story_points": [
+                {{"story_number": "s-12345", "points": "3"}},
+                {{"story_number": "S-54321", "points": "8"}}
+            ]}}"#
+        )
+        .unwrap();
+
+        let client = FileTrackerClient::new(path.to_str().unwrap());
+        let points = client
+            .fetch_points(&["S-12345".to_string(), "S-00000".to_string()])
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(points.story_points.len(), 1);
+        assert_eq!(points.story_points[0].story_number, "S-12345");
+        assert_eq!(points.story_points[0].points, "3");
+    }
+
+    #[test]
+    fn format_points_drops_trailing_zero() {
+        assert_eq!(
+            format_points(&serde_json::json!(5.0)),
+            Some("5".to_string())
+        );
+        assert_eq!(
+            format_points(&serde_json::json!(2.5)),
+            Some("2.5".to_string())
+        );
+        assert_eq!(format_points(&serde_json::json!(null)), None);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::errors::TrackerError;
+use crate::repo::diff::{StoryPoint, StoryPointCollection};
+use crate::tracker::{
+    format_points, get_agent, get_json, select_requested, TrackerClient, BATCH_SIZE,
+};
+
+/// Fetches estimates for stories and defects through the Version1 rest-1.v1 data API.
+pub struct VersionOneClient {
+    pub base_url: String,
+    pub token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl VersionOneClient {
+    pub fn new(base_url: &str, token: Option<String>) -> VersionOneClient {
+        VersionOneClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+            agent: get_agent(),
+        }
+    }
+
+    fn fetch_batch(&self, story_numbers: &[String]) -> Result<Vec<StoryPoint>, TrackerError> {
+        // Version1 joins where clauses with '|' for OR
+        let filter = story_numbers
+            .iter()
+            .map(|story_number| format!("Number='{}'", story_number))
+            .collect::<Vec<String>>()
+            .join("|");
+
+        let mut request = self
+            .agent
+            .get(&format!("{}/rest-1.v1/Data/PrimaryWorkitem", self.base_url))
+            .query("sel", "Number,Estimate")
+            .query("where", &filter)
+            .set("Accept", "application/json");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+
+        let body = get_json(request)?;
+        let assets = body["Assets"].as_array().cloned().unwrap_or_default();
+
+        Ok(assets
+            .iter()
+            .filter_map(|asset| {
+                let attributes = &asset["Attributes"];
+                let number = attributes["Number"]["value"].as_str()?;
+                let points = format_points(&attributes["Estimate"]["value"])?;
+                Some(StoryPoint {
+                    story_number: number.to_string(),
+                    points,
+                })
+            })
+            .collect())
+    }
+}
+
+impl TrackerClient for VersionOneClient {
+    fn fetch_points(&self, story_numbers: &[String]) -> Result<StoryPointCollection, TrackerError> {
+        let mut found = Vec::new();
+        for batch in story_numbers.chunks(BATCH_SIZE) {
+            found.extend(self.fetch_batch(batch)?);
+        }
+
+        Ok(select_requested(story_numbers, found))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::test_server::TestServer;
+
+    #[test]
+    fn fetches_estimates_for_requested_numbers() {
+        let server = TestServer::start(vec![(
+            200,
+            r#"{"Assets": [
+                {"Attributes": {"Number": {"value": "S-12345"}, "Estimate": {"value": 3.0}}},
+                {"Attributes": {"Number": {"value": "D-54321"}, "Estimate": {"value": 0.5}}},
+                {"Attributes": {"Number": {"value": "S-11111"}, "Estimate": {"value": null}}}
+            ]}"#,
+        )]);
+
+        let client = VersionOneClient::new(&server.url, Some("secret".to_string()));
+        let points = client
+            .fetch_points(&[
+                "S-12345".to_string(),
+                "D-54321".to_string(),
+                "S-11111".to_string(),
+            ])
+            .unwrap();
+
+        let found: Vec<(&str, &str)> = points
+            .story_points
+            .iter()
+            .map(|point| (point.story_number.as_str(), point.points.as_str()))
+            .collect();
+        assert_eq!(found, vec![("S-12345", "3"), ("D-54321", "0.5")]);
+
+        let requests = server.requests();
+        assert!(
+            requests[0].starts_with("GET /rest-1.v1/Data/PrimaryWorkitem?sel=Number%2CEstimate")
+        );
+        assert!(requests[0].contains("where=Number%3D%27S-12345%27%7CNumber%3D%27D-54321%27"));
+        assert!(requests[0]
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+    }
+
+    #[test]
+    fn requests_large_backlogs_in_batches() {
+        let server =
+            TestServer::start(vec![(200, r#"{"Assets": []}"#), (200, r#"{"Assets": []}"#)]);
+
+        let story_numbers: Vec<String> = (0..BATCH_SIZE + 1)
+            .map(|number| format!("S-{:05}", number))
+            .collect();
+        let client = VersionOneClient::new(&server.url, None);
+        client.fetch_points(&story_numbers).unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+    }
+}
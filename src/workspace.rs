@@ -0,0 +1,100 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use story_line_counter::errors::{CliError, FileError, InputError};
+
+/// The repos to run over together, from a manifest file or the paths given on the command line.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    pub repos: Vec<WorkspaceRepo>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceRepo {
+    pub path: String,
+    /// The name diffs are tagged with. Defaults to the name of the repo's directory.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The branch to walk, in place of the one given on the command line or in a config file.
+    #[serde(default)]
+    pub branch: Option<String>,
+}
+
+impl Workspace {
+    /// Reads a manifest like `{"repos": [{"path": "../api", "name": "api", "branch": "main"}]}`.
+    /// Repo paths are relative to the manifest.
+    pub fn load(manifest_path: &str) -> Result<Workspace, CliError> {
+        let bad_file =
+            |error: String| CliError::BadConfigFile(FileError::new(manifest_path, error));
+
+        let contents =
+            fs::read_to_string(manifest_path).map_err(|error| bad_file(error.to_string()))?;
+        let mut workspace: Workspace =
+            serde_json::from_str(&contents).map_err(|error| bad_file(error.to_string()))?;
+        if workspace.repos.is_empty() {
+            return Err(bad_file("The manifest lists no repos.".to_string()));
+        }
+
+        let directory = Path::new(manifest_path)
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        for repo in workspace.repos.iter_mut() {
+            repo.path = directory.join(&repo.path).to_string_lossy().to_string();
+        }
+
+        workspace.name_repos()?;
+        Ok(workspace)
+    }
+
+    pub fn from_paths(paths: &[String]) -> Result<Workspace, CliError> {
+        let mut workspace = Workspace {
+            repos: paths
+                .iter()
+                .map(|path| WorkspaceRepo {
+                    path: path.to_string(),
+                    name: None,
+                    branch: None,
+                })
+                .collect(),
+        };
+
+        workspace.name_repos()?;
+        Ok(workspace)
+    }
+
+    // Names unnamed repos after their directories, failing if two repos would share a name
+    fn name_repos(&mut self) -> Result<(), CliError> {
+        let mut names: HashSet<String> = HashSet::new();
+
+        for repo in self.repos.iter_mut() {
+            let name = match &repo.name {
+                Some(name) => name.to_string(),
+                None => get_directory_name(&repo.path),
+            };
+            if !names.insert(name.clone()) {
+                return Err(CliError::from(InputError::from(format!(
+                    "More than one repo is named '{}'. Name them in a workspace manifest.",
+                    name
+                ))));
+            }
+            repo.name = Some(name);
+        }
+
+        Ok(())
+    }
+}
+
+// e.g. 'api' for '../api', or for '.' run from inside it
+fn get_directory_name(path: &str) -> String {
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| path.to_string())
+}
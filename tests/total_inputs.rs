@@ -0,0 +1,124 @@
+//! Runs `total` on collect output in each shape it may take, from files and from stdin.
+
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+fn diff(commit: &str, story_number: &str, insertions: usize) -> String {
+    format!(
+        r#"{{"commit":"{}","story_number":["{}"],"points":"","first_summary":"","second_summary":"{} change","files_changed":1,"insertions":{},"deletions":1}}"#,
+        commit, story_number, story_number, insertions
+    )
+}
+
+fn total(args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_story-line-counter"))
+        .arg("total")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut child_stdin = child.stdin.take().unwrap();
+    child_stdin
+        .write_all(stdin.unwrap_or_default().as_bytes())
+        .unwrap();
+    drop(child_stdin);
+
+    child.wait_with_output().unwrap()
+}
+
+// Each story's insertions and commits, from the JSON totals
+fn counts(output: &Output) -> Vec<(String, u64, u64)> {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let totals: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let mut counts: Vec<(String, u64, u64)> = totals["totals"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(story_number, total)| {
+            (
+                story_number.to_string(),
+                total["insertions"].as_u64().unwrap(),
+                total["total_diff_results"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    counts.sort();
+    counts
+}
+
+#[test]
+fn totals_collections_and_json_lines_from_files() {
+    let dir = TempDir::new().unwrap();
+    let collection = dir.path().join("collection.json");
+    let lines = dir.path().join("lines.jsonl");
+    fs::write(
+        &collection,
+        format!(
+            "{{\"diffs\":[{}]}}\n{{\"diffs\":[{}]}}",
+            diff("a1", "S-10001", 3),
+            diff("a2", "S-10002", 5)
+        ),
+    )
+    .unwrap();
+    fs::write(
+        &lines,
+        format!(
+            "{}\n{}\n",
+            diff("b1", "S-10001", 2),
+            diff("b2", "D-20001", 7)
+        ),
+    )
+    .unwrap();
+
+    let output = total(
+        &[collection.to_str().unwrap(), lines.to_str().unwrap()],
+        None,
+    );
+
+    assert_eq!(
+        counts(&output),
+        vec![
+            ("D-20001".to_string(), 7, 1),
+            ("S-10001".to_string(), 5, 2),
+            ("S-10002".to_string(), 5, 1),
+        ]
+    );
+}
+
+#[test]
+fn totals_collections_and_json_lines_from_stdin() {
+    let collection = format!("{{\"diffs\":[{}]}}", diff("a1", "S-10001", 3));
+    let lines = format!(
+        "{}\n{}\n",
+        diff("b1", "S-10001", 2),
+        diff("b2", "D-20001", 7)
+    );
+
+    assert_eq!(
+        counts(&total(&[], Some(&collection))),
+        vec![("S-10001".to_string(), 3, 1)]
+    );
+    assert_eq!(
+        counts(&total(&["-"], Some(&lines))),
+        vec![("D-20001".to_string(), 7, 1), ("S-10001".to_string(), 2, 1)]
+    );
+}
+
+#[test]
+fn reports_input_that_isnt_collect_output() {
+    let output = total(&[], Some("{\"diffs\":[{\"commit\":\"a1\"}]}"));
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("stdin"));
+}